@@ -0,0 +1,30 @@
+use std::time::Duration;
+
+use bevy_mod_indigauge::{
+  ig_error, ig_info,
+  prelude::{IndigaugeClient, IndigaugeLogLevel, IndigaugeMode},
+};
+
+fn main() {
+  // Plain Rust binaries (launchers, editors, build tools) can report to the same project as the game.
+  let client = IndigaugeClient::builder("YOUR_PUBLIC_KEY", "Your game name", "1.0.0")
+    .platform("launcher")
+    // Optional: Set mode (Defaults to live). Dev mode does not send events to the server.
+    .mode(IndigaugeMode::Dev)
+    .log_level(IndigaugeLogLevel::Info)
+    .flush_interval(Duration::from_secs(2))
+    .start()
+    .expect("Failed to start Indigauge session");
+
+  ig_info!("launcher.open");
+
+  for step in 1..=3 {
+    std::thread::sleep(Duration::from_millis(500));
+    ig_info!("launcher.update", { "step": step });
+  }
+
+  ig_error!("launcher.failed", { "reason": "example" });
+
+  // Flushes buffered events and ends the session. Dropping the client does the same.
+  client.end();
+}
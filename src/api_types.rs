@@ -1,6 +1,19 @@
-use std::ops::Deref;
+use std::{borrow::Cow, collections::BTreeMap, ops::Deref};
 
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Serialize, Serializer};
+
+use crate::{
+  event::{metadata::EventMetadata, resources::BufferedEvents},
+  session::{resources::IndigaugeActivity, types::SessionEndReason},
+};
+
+/// Serializes milliseconds as a `u64` (saturating), so that every wire format encodes them as a plain integer.
+fn serialize_millis<S>(millis: &u128, serializer: S) -> Result<S::Ok, S::Error>
+where
+  S: Serializer,
+{
+  serializer.serialize_u64(u64::try_from(*millis).unwrap_or(u64::MAX))
+}
 
 #[derive(Debug, Deserialize)]
 #[serde(untagged)]
@@ -32,12 +45,16 @@ impl Deref for IdResponse {
 #[serde(rename_all = "camelCase")]
 pub struct StartSessionResponse {
   pub session_token: String,
+  /// Remote config document for the game, see [`IndigaugeRemoteConfig`](crate::prelude::IndigaugeRemoteConfig).
+  #[serde(default)]
+  pub config: Option<serde_json::Value>,
 }
 
 impl StartSessionResponse {
   pub fn dev() -> Self {
     Self {
       session_token: "dev".to_string(),
+      config: None,
     }
   }
 }
@@ -46,7 +63,17 @@ impl StartSessionResponse {
 #[serde(rename_all = "camelCase")]
 pub struct StartSessionPayload<'a> {
   pub client_version: &'a str,
-  pub player_id: Option<&'a String>,
+  /// Id generated on the client when the session was started. Used to reconcile data recorded before the
+  /// session was registered.
+  pub local_session_id: Option<&'a str>,
+  /// Token of the session this one continues, after the server stopped accepting it.
+  pub continues_session: Option<&'a str>,
+  /// UTC unix timestamp in milliseconds of when the session was started on the client.
+  pub started_at: u64,
+  /// The game-provided player id once the player was identified, otherwise the anonymous id of the device.
+  pub player_id: Option<&'a str>,
+  /// Anonymous id of the device, sent alongside a game-provided player id so that the two stay linked.
+  pub anonymous_id: Option<&'a str>,
   pub platform: Option<&'a String>,
   pub os: Option<&'a str>,
   pub cpu_family: Option<&'a String>,
@@ -55,40 +82,193 @@ pub struct StartSessionPayload<'a> {
   pub gpu: Option<&'a String>,
 }
 
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct EndSessionPayload<'a> {
+  pub reason: &'a str,
+  /// Reason defined by the game, if `reason` is `custom`.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub custom_reason: Option<&'a str>,
+  /// Exit code if the app exited with an error.
+  pub exit_code: Option<u8>,
+  /// Final metadata provided when the session was ended.
+  pub metadata: Option<&'a serde_json::Value>,
+  /// Time the player was active, in milliseconds.
+  pub active_ms: Option<u64>,
+  /// Time the player was idle, in milliseconds.
+  pub idle_ms: Option<u64>,
+}
+
+impl<'a> EndSessionPayload<'a> {
+  pub fn new(reason: &'a SessionEndReason, metadata: Option<&'a serde_json::Value>) -> Self {
+    Self {
+      reason: reason.as_str(),
+      custom_reason: reason.custom_reason(),
+      exit_code: reason.exit_code(),
+      metadata,
+      active_ms: None,
+      idle_ms: None,
+    }
+  }
+
+  pub fn with_activity(mut self, activity: Option<&IndigaugeActivity>) -> Self {
+    self.active_ms = activity.map(|activity| activity.active_time().as_millis() as u64);
+    self.idle_ms = activity.map(|activity| activity.idle_time().as_millis() as u64);
+    self
+  }
+}
+
+/// Reported when the game identifies the player or resets the identity, see
+/// [`IndigaugePlayer`](crate::prelude::IndigaugePlayer).
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct PlayerIdentityPayload {
+  pub player_id: String,
+  pub previous_player_id: String,
+  /// Anonymous id linked to the player id, so that data recorded before the player logged in is attributed to them.
+  pub anonymous_id: Option<String>,
+  /// UTC unix timestamp in milliseconds of when the identity changed.
+  pub changed_at: u64,
+}
+
+#[derive(Serialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct HeartbeatPayload {
+  /// Time the player was active so far, in milliseconds.
+  pub active_ms: Option<u64>,
+  /// Time the player was idle so far, in milliseconds.
+  pub idle_ms: Option<u64>,
+  /// Whether the player is currently idle.
+  pub idle: Option<bool>,
+  /// Average frames per second since the last heartbeat.
+  pub fps: Option<f32>,
+  /// Frame time percentiles since the last heartbeat.
+  pub frame_time_ms: Option<FrameTimePercentiles>,
+  /// Resident memory of the game process in bytes. Not available on wasm.
+  pub memory_bytes: Option<u64>,
+  /// Context about the current state of the game, see [`HeartbeatContext`](crate::prelude::HeartbeatContext).
+  #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+  pub context: BTreeMap<String, String>,
+  pub queue: Option<HeartbeatQueueStats>,
+}
+
+impl HeartbeatPayload {
+  pub fn new(activity: Option<&IndigaugeActivity>) -> Self {
+    Self {
+      active_ms: activity.map(|activity| activity.active_time().as_millis() as u64),
+      idle_ms: activity.map(|activity| activity.idle_time().as_millis() as u64),
+      idle: activity.map(IndigaugeActivity::is_idle),
+      ..Default::default()
+    }
+  }
+}
+
+/// Frame times in milliseconds.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+pub struct FrameTimePercentiles {
+  pub p50: f32,
+  pub p95: f32,
+  pub p99: f32,
+  pub max: f32,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct HeartbeatQueueStats {
+  /// Regular events waiting to be sent.
+  pub buffered: usize,
+  /// Priority events waiting to be sent.
+  pub priority: usize,
+  /// Serialized size of the regular events waiting to be sent, in bytes.
+  pub buffered_bytes: usize,
+  /// Requests that are still being encoded by the delivery worker.
+  pub pending_deliveries: usize,
+}
+
+impl HeartbeatQueueStats {
+  pub(crate) fn new(buffered_events: &BufferedEvents, pending_deliveries: usize) -> Self {
+    Self {
+      buffered: buffered_events.events().len(),
+      priority: buffered_events.priority_events().len(),
+      buffered_bytes: buffered_events.events_size(),
+      pending_deliveries,
+    }
+  }
+}
+
 #[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
 pub struct BatchEventPayload {
   pub events: Vec<EventPayload>,
+  /// Id generated on the client for the session the events were recorded in.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub local_session_id: Option<&'static str>,
+}
+
+impl BatchEventPayload {
+  /// Creates a batch for the current session.
+  pub fn new(events: Vec<EventPayload>) -> Self {
+    Self {
+      events,
+      local_session_id: crate::session::local_session_id(),
+    }
+  }
 }
 
 #[derive(Serialize, Clone, Debug, PartialEq)]
 #[serde(rename_all = "camelCase")]
 pub struct EventPayload {
   /// The type of the event. Event type must be in the format 'namespace.type'
-  pub event_type: String,
-  /// Metadata associated with the event.
-  pub metadata: Option<serde_json::Value>,
+  pub event_type: Cow<'static, str>,
+  /// Metadata associated with the event. Serialized when the batch is sent.
+  pub metadata: Option<EventMetadata>,
   /// The level of the event.
   pub level: &'static str,
   /// Defaults to elapsed time since session start
+  #[serde(serialize_with = "serialize_millis")]
   pub elapsed_ms: u128,
+  /// UTC unix timestamp in milliseconds of when the event was enqueued.
+  pub timestamp: u64,
+  /// Sequence number of the event within the session, starting at 0. Events that are sent again with a continuation
+  /// session are renumbered. Events discarded on the client, e.g. by a full queue, sampling or the metadata size
+  /// policy, leave gaps.
+  pub seq: u64,
   /// Defaults to a random string
   pub idempotency_key: Option<String>,
   pub context: Option<EventPayloadCtx>,
+  /// Game time at which the event was handled. Only set if game time stamping is enabled.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub game_time: Option<EventGameTime>,
 }
 
 #[derive(Serialize, Clone, Debug, PartialEq)]
 pub struct EventPayloadCtx {
-  pub file: String,
+  pub file: Cow<'static, str>,
   pub line: u32,
   pub module: Option<&'static str>,
 }
 
+#[derive(Serialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct EventGameTime {
+  /// Bevy frame count (`FrameCount`).
+  pub frame: u32,
+  /// Elapsed `Time<Virtual>` in milliseconds. Pausing or scaling virtual time affects this value.
+  #[serde(serialize_with = "serialize_millis")]
+  pub virtual_elapsed_ms: u128,
+  /// Number of fixed timesteps that have run, counted in `FixedFirst`.
+  pub fixed_tick: u64,
+}
+
 #[derive(Serialize, Clone, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct FeedbackPayload<'a> {
   pub message: &'a str,
   /// Defaults to elapsed time since session start
+  #[serde(serialize_with = "serialize_millis")]
   pub elapsed_ms: u128,
   pub question: Option<&'a String>,
   pub category: String,
+  /// Id generated on the client for the session the feedback was given in.
+  pub local_session_id: Option<&'static str>,
 }
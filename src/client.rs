@@ -0,0 +1,569 @@
+use std::{
+  env::consts::OS,
+  thread::JoinHandle,
+  time::{Duration, Instant},
+};
+
+use crossbeam_channel::{Receiver, Sender, bounded, select};
+use reqwest::{
+  StatusCode,
+  blocking::{Client, Response},
+  header::RETRY_AFTER,
+};
+use serde::Serialize;
+use tracing::{Dispatch, dispatcher, error, info, warn};
+
+use crate::{
+  api_types::{
+    ApiResponse, BatchEventPayload, EndSessionPayload, EventPayload, HeartbeatPayload, HeartbeatQueueStats,
+    StartSessionPayload, StartSessionResponse,
+  },
+  config::{IndigaugeConfig, IndigaugeLogLevel, IndigaugeMode},
+  delivery::{BatchDelivery, BatchResponse, handle_batch_response},
+  event::{
+    resources::{BufferedEvents, QueuedEvent},
+    utils::{GLOBAL_TX, init_event_queue},
+  },
+  identity::IndigaugePlayer,
+  session::{
+    begin_session, event_seq_generation, init_local_session_id, install_panic_hook, restart_event_seq,
+    types::SessionEndReason, utils::ProcessMemory,
+  },
+  transport::{EncodedBody, IndigaugeCompression, IndigaugeWireFormat, TransportState, encode_body},
+  utils::{select, unix_timestamp_ms},
+};
+
+/// Errors that can occur when starting an [`IndigaugeClient`].
+#[derive(Debug)]
+pub enum IndigaugeClientError {
+  /// A session was already started in this process, by a client or by the Bevy plugin.
+  AlreadyStarted,
+  /// Live mode requires a public key.
+  MissingPublicKey,
+  Request(reqwest::Error),
+  /// The server refused to start the session.
+  Rejected { code: String, message: String },
+  /// The background thread that sends events could not be started.
+  Spawn(std::io::Error),
+}
+
+impl std::fmt::Display for IndigaugeClientError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      IndigaugeClientError::AlreadyStarted => write!(f, "an Indigauge session was already started in this process"),
+      IndigaugeClientError::MissingPublicKey => write!(f, "Indigauge public key is not set for live-mode"),
+      IndigaugeClientError::Request(error) => write!(f, "failed to start session: {error}"),
+      IndigaugeClientError::Rejected { code, message } => write!(f, "failed to start session: {code}: {message}"),
+      IndigaugeClientError::Spawn(error) => write!(f, "failed to start the background thread: {error}"),
+    }
+  }
+}
+
+impl std::error::Error for IndigaugeClientError {}
+
+/// Builder for an [`IndigaugeClient`].
+pub struct IndigaugeClientBuilder {
+  config: IndigaugeConfig,
+  log_level: IndigaugeLogLevel,
+  mode: IndigaugeMode,
+  platform: Option<String>,
+}
+
+impl IndigaugeClientBuilder {
+  pub fn new(public_key: impl Into<String>, game_name: impl Into<String>, game_version: impl Into<String>) -> Self {
+    Self {
+      config: IndigaugeConfig::new(game_name, public_key, game_version),
+      log_level: IndigaugeLogLevel::Info,
+      mode: IndigaugeMode::default(),
+      platform: None,
+    }
+  }
+
+  pub fn log_level(mut self, log_level: IndigaugeLogLevel) -> Self {
+    self.log_level = log_level;
+    self
+  }
+
+  pub fn mode(mut self, mode: IndigaugeMode) -> Self {
+    self.mode = mode;
+    self
+  }
+
+  /// Platform reported when the session is started, e.g. `"launcher"` or `"editor"`.
+  pub fn platform(mut self, platform: impl Into<String>) -> Self {
+    self.platform = Some(platform.into());
+    self
+  }
+
+  /// How often buffered events are flushed. Defaults to 10 seconds.
+  pub fn flush_interval(mut self, flush_interval: Duration) -> Self {
+    self.config.flush_interval = flush_interval;
+    self
+  }
+
+  /// How often a heartbeat is sent. Defaults to 30 seconds.
+  pub fn heartbeat_interval(mut self, heartbeat_interval: Duration) -> Self {
+    self.config.heartbeat_interval = heartbeat_interval;
+    self
+  }
+
+  /// Event types that are flushed immediately in a small priority batch, ahead of queued events.
+  /// Error events are always prioritized.
+  pub fn priority_event_types<T>(mut self, event_types: Vec<T>) -> Self
+  where
+    T: Into<String>,
+  {
+    self.config.priority_event_types = event_types.into_iter().map(Into::into).collect();
+    self
+  }
+
+  /// Compression for event batches of at least `threshold` bytes.
+  pub fn compression(mut self, compression: IndigaugeCompression, threshold: usize) -> Self {
+    self.config.compression = compression;
+    self.config.compression_threshold = threshold;
+    self
+  }
+
+  pub fn wire_format(mut self, wire_format: IndigaugeWireFormat) -> Self {
+    self.config.wire_format = wire_format;
+    self
+  }
+
+  /// Starts the session and the background thread that batches and sends events.
+  ///
+  /// Blocks until the session is started. Once started, the `ig_*!` macros send events to this session.
+  pub fn start(self) -> Result<IndigaugeClient, IndigaugeClientError> {
+    if self.mode == IndigaugeMode::Disabled {
+      return Ok(IndigaugeClient {
+        shutdown: None,
+        handle: None,
+      });
+    }
+
+    if GLOBAL_TX.get().is_some() {
+      return Err(IndigaugeClientError::AlreadyStarted);
+    }
+
+    let http = Client::builder()
+      .timeout(self.config.request_timeout)
+      .build()
+      .map_err(IndigaugeClientError::Request)?;
+
+    let session_token = match self.mode {
+      IndigaugeMode::Live => start_session(&http, &self.config, self.platform.as_ref(), None)?,
+      _ => StartSessionResponse::dev().session_token,
+    };
+
+    let events = init_event_queue(self.config.max_queue).ok_or(IndigaugeClientError::AlreadyStarted)?;
+    begin_session(&self.config.api_base, &session_token).map_err(|_| IndigaugeClientError::AlreadyStarted)?;
+
+    let log_dispatch = stderr_log_dispatch();
+    with_log_dispatch(log_dispatch.as_ref(), || {
+      if self.log_level <= IndigaugeLogLevel::Info {
+        match self.mode {
+          IndigaugeMode::Live => info!(message = "Indigauge session started"),
+          _ => info!(message = "DEVMODE: Indigauge session started"),
+        }
+      }
+    });
+
+    let (shutdown_tx, shutdown_rx) = bounded::<()>(0);
+    let mut worker = ClientWorker {
+      http,
+      config: self.config,
+      log_level: self.log_level,
+      mode: self.mode,
+      platform: self.platform,
+      session_token,
+      buffered_events: BufferedEvents::default(),
+      transport: TransportState::default(),
+      process_memory: ProcessMemory::default(),
+      ending: false,
+    };
+
+    let handle = std::thread::Builder::new()
+      .name("indigauge-client".to_string())
+      .spawn(move || with_log_dispatch(log_dispatch.as_ref(), || worker.run(events, shutdown_rx)))
+      .map_err(IndigaugeClientError::Spawn)?;
+
+    Ok(IndigaugeClient {
+      shutdown: Some(shutdown_tx),
+      handle: Some(handle),
+    })
+  }
+}
+
+/// Starts a session, or a continuation of the session with the `continues_session` token if it was rejected.
+fn start_session(
+  http: &Client,
+  config: &IndigaugeConfig,
+  platform: Option<&String>,
+  continues_session: Option<&str>,
+) -> Result<String, IndigaugeClientError> {
+  if config.public_key.is_empty() {
+    return Err(IndigaugeClientError::MissingPublicKey);
+  }
+
+  let player = IndigaugePlayer::load(&config.game_name);
+  let payload = StartSessionPayload {
+    client_version: &config.game_version,
+    local_session_id: Some(init_local_session_id()),
+    continues_session,
+    started_at: unix_timestamp_ms(),
+    player_id: Some(player.id()),
+    anonymous_id: player.custom_id().map(|_| player.anonymous_id()),
+    platform,
+    os: Some(OS),
+    cpu_family: None,
+    cores: None,
+    memory: None,
+    gpu: None,
+  };
+
+  let response = http
+    .post(format!("{}/v1/sessions/start", config.api_base))
+    .header("X-Indigauge-Key", &config.public_key)
+    .json(&payload)
+    .send()
+    .and_then(|response| response.json::<ApiResponse<StartSessionResponse>>())
+    .map_err(IndigaugeClientError::Request)?;
+
+  match response {
+    ApiResponse::Ok(response) => Ok(response.session_token),
+    ApiResponse::Err(error_body) => Err(IndigaugeClientError::Rejected {
+      code: error_body.code,
+      message: error_body.message,
+    }),
+  }
+}
+
+/// Indigauge client for processes that do not run a Bevy app, such as launchers, editors and build tools.
+///
+/// Events are sent with the same `ig_*!` macros as in a Bevy game, and are batched and delivered on a
+/// background thread. Only one session, either a client or the Bevy plugin, can be active per process.
+/// The session is ended when the client is dropped.
+///
+/// # Example
+/// ```no_run
+/// use bevy_mod_indigauge::{ig_info, prelude::*};
+///
+/// let client = IndigaugeClient::builder("YOUR_PUBLIC_KEY", "My Game Launcher", "1.0.0")
+///   .platform("launcher")
+///   .start()
+///   .expect("Indigauge session");
+///
+/// ig_info!("launcher.open");
+///
+/// client.end();
+/// ```
+pub struct IndigaugeClient {
+  shutdown: Option<Sender<()>>,
+  handle: Option<JoinHandle<()>>,
+}
+
+impl IndigaugeClient {
+  pub fn builder(
+    public_key: impl Into<String>,
+    game_name: impl Into<String>,
+    game_version: impl Into<String>,
+  ) -> IndigaugeClientBuilder {
+    IndigaugeClientBuilder::new(public_key, game_name, game_version)
+  }
+
+  /// Flushes all buffered events and ends the session. Blocks until done.
+  pub fn end(mut self) {
+    self.shutdown();
+  }
+
+  fn shutdown(&mut self) {
+    // Dropping the sender wakes up the background thread, which flushes and ends the session.
+    self.shutdown.take();
+
+    if let Some(handle) = self.handle.take() {
+      let _ = handle.join();
+    }
+  }
+}
+
+impl Drop for IndigaugeClient {
+  fn drop(&mut self) {
+    self.shutdown();
+  }
+}
+
+/// Processes that don't run a Bevy app usually don't install a tracing subscriber, which would silence the client.
+/// In that case the client logs to stderr.
+fn stderr_log_dispatch() -> Option<Dispatch> {
+  (!dispatcher::has_been_set())
+    .then(|| Dispatch::new(tracing_subscriber::fmt().with_writer(std::io::stderr).finish()))
+}
+
+fn with_log_dispatch<T>(dispatch: Option<&Dispatch>, f: impl FnOnce() -> T) -> T {
+  match dispatch {
+    Some(dispatch) => dispatcher::with_default(dispatch, f),
+    None => f(),
+  }
+}
+
+struct ClientWorker {
+  http: Client,
+  config: IndigaugeConfig,
+  log_level: IndigaugeLogLevel,
+  mode: IndigaugeMode,
+  platform: Option<String>,
+  session_token: String,
+  buffered_events: BufferedEvents,
+  transport: TransportState,
+  process_memory: ProcessMemory,
+  /// Set once the session is ending. Batches that fail from then on are dropped, there is no later flush to retry
+  /// them in.
+  ending: bool,
+}
+
+impl ClientWorker {
+  fn run(&mut self, events: Receiver<QueuedEvent>, shutdown: Receiver<()>) {
+    let mut last_flush = Instant::now();
+    let mut last_heartbeat = Instant::now();
+
+    loop {
+      let next_heartbeat = self.config.heartbeat_interval.saturating_sub(last_heartbeat.elapsed());
+      let next_flush = self.config.flush_interval.saturating_sub(last_flush.elapsed());
+      // Batches are held back while rate limited, so there is nothing to flush before then.
+      let next_delivery = match self.transport.rate_limited_until {
+        Some(until) if self.transport.is_rate_limited() => until.saturating_duration_since(Instant::now()),
+        _ if self.buffered_events.priority_events().is_empty() => next_flush,
+        _ => next_flush.min(self.config.priority_flush_interval),
+      };
+      let timeout = next_heartbeat.min(next_delivery);
+
+      select! {
+        recv(events) -> event => match event {
+          Ok(event) => self.buffer(event),
+          Err(_) => break,
+        },
+        recv(shutdown) -> _ => break,
+        default(timeout) => {},
+      }
+
+      if last_heartbeat.elapsed() >= self.config.heartbeat_interval {
+        last_heartbeat = Instant::now();
+        self.send_heartbeat();
+      }
+
+      if self.transport.is_rate_limited() {
+        continue;
+      }
+
+      if self.buffered_events.is_priority_flush_due(&self.config) {
+        let events = self.buffered_events.take_priority_batch(&self.config);
+        self.send_event_batch(BatchEventPayload::new(events));
+      }
+
+      if self.buffered_events.is_batch_full(&self.config) {
+        let events = self.buffered_events.take_batch(&self.config);
+        self.send_event_batch(BatchEventPayload::new(events));
+      }
+
+      if last_flush.elapsed() >= self.config.flush_interval {
+        last_flush = Instant::now();
+
+        if !self.buffered_events.is_empty() {
+          let events = self.buffered_events.take_batch(&self.config);
+          self.send_event_batch(BatchEventPayload::new(events));
+        }
+      }
+    }
+
+    self.ending = true;
+    for event in events.try_iter() {
+      self.buffer(event);
+    }
+
+    while !self.buffered_events.is_empty() {
+      let events = self.buffered_events.take_batch(&self.config);
+      self.send_event_batch(BatchEventPayload::new(events));
+    }
+
+    self.post("sessions/end", &EndSessionPayload::new(&SessionEndReason::Ended, None));
+  }
+
+  fn buffer(&mut self, mut event: QueuedEvent) {
+    if event.prepare(&self.config, &self.log_level) {
+      self.buffered_events.push(event, &self.config);
+    }
+  }
+
+  // The dev mode arm keeps the log level check in its body, like the other log guards.
+  #[allow(clippy::collapsible_match)]
+  fn send_heartbeat(&mut self) {
+    let payload = HeartbeatPayload {
+      memory_bytes: self.process_memory.resident_bytes(),
+      queue: Some(HeartbeatQueueStats::new(&self.buffered_events, 0)),
+      ..Default::default()
+    };
+
+    match self.mode {
+      IndigaugeMode::Live => self.post("sessions/heartbeat", &payload),
+      IndigaugeMode::Dev => {
+        if self.log_level <= IndigaugeLogLevel::Info {
+          info!(message = "DEVMODE: heartbeat", ?payload);
+        }
+      },
+      _ => {},
+    }
+  }
+
+  /// Buffers the events of a failed batch again. Events numbered for an earlier `generation` of the session are
+  /// renumbered.
+  fn requeue(&mut self, events: Vec<EventPayload>, generation: u64) {
+    for payload in events {
+      let mut event = QueuedEvent::new(payload, generation);
+      event.measure();
+      self.buffered_events.push(event, &self.config);
+    }
+  }
+
+  /// Starts a continuation session after the session token was rejected. Returns whether it was started.
+  fn continue_session(&mut self) -> bool {
+    if self.ending {
+      return false;
+    }
+
+    match start_session(&self.http, &self.config, self.platform.as_ref(), Some(&self.session_token)) {
+      Ok(session_token) => {
+        if self.log_level <= IndigaugeLogLevel::Warn {
+          warn!("Indigauge session token was rejected, started a continuation session");
+        }
+        restart_event_seq();
+        install_panic_hook(&self.config.api_base, &session_token);
+        self.session_token = session_token;
+        true
+      },
+      Err(error) => {
+        if self.log_level <= IndigaugeLogLevel::Error {
+          error!(message = "Failed to start a continuation session", %error);
+        }
+        false
+      },
+    }
+  }
+
+  fn send_event_batch(&mut self, batch: BatchEventPayload) {
+    if self.mode != IndigaugeMode::Live {
+      if self.log_level <= IndigaugeLogLevel::Info {
+        info!(message = "DEVMODE: sending event batch", count = batch.events.len());
+      }
+      return;
+    }
+
+    let wire_format = select(IndigaugeWireFormat::Json, self.config.wire_format, self.transport.wire_format_rejected);
+    let compression = select(IndigaugeCompression::None, self.config.compression, self.transport.compression_rejected);
+
+    let body = match encode_body(&batch, wire_format, compression, self.config.compression_threshold) {
+      Ok(body) => body,
+      Err(error) => {
+        if self.log_level <= IndigaugeLogLevel::Error {
+          error!(message = "Failed to create event batch request", %error);
+        }
+        return;
+      },
+    };
+    let encoding = body.encoding;
+
+    match self.post_body("events/batch", body) {
+      Ok(response) => {
+        let response = BatchResponse::Status {
+          status: response.status(),
+          retry_after: response.headers().get(RETRY_AFTER).and_then(|value| value.to_str().ok()),
+        };
+        handle_batch_response(self, batch, encoding, response);
+      },
+      Err(error) => handle_batch_response(self, batch, encoding, BatchResponse::Failed(&error)),
+    }
+  }
+
+  fn post_body(&self, url: &str, body: EncodedBody) -> Result<Response, reqwest::Error> {
+    let mut request = self
+      .http
+      .post(format!("{}/v1/{}", self.config.api_base, url))
+      .header("Content-Type", body.content_type)
+      .header("X-Indigauge-Key", &self.session_token);
+
+    if let Some(content_encoding) = body.content_encoding {
+      request = request.header("Content-Encoding", content_encoding);
+    }
+
+    request.body(body.bytes).send()
+  }
+
+  fn post<S>(&mut self, url: &str, payload: &S)
+  where
+    S: Serialize,
+  {
+    if self.mode != IndigaugeMode::Live {
+      return;
+    }
+
+    let result = self
+      .http
+      .post(format!("{}/v1/{}", self.config.api_base, url))
+      .header("X-Indigauge-Key", &self.session_token)
+      .json(payload)
+      .send();
+
+    match result {
+      Ok(response) if response.status() == StatusCode::UNAUTHORIZED => {
+        self.continue_session();
+      },
+      Ok(response) if !response.status().is_success() && self.log_level <= IndigaugeLogLevel::Error => {
+        error!(message = "Indigauge request failed", url, status = %response.status());
+      },
+      Err(error) if self.log_level <= IndigaugeLogLevel::Error => {
+        error!(message = "Indigauge request failed", url, %error);
+      },
+      _ => {},
+    }
+  }
+}
+
+impl BatchDelivery for ClientWorker {
+  fn log_level(&self) -> &IndigaugeLogLevel {
+    &self.log_level
+  }
+
+  fn transport(&mut self) -> &mut TransportState {
+    &mut self.transport
+  }
+
+  fn retry_interval(&self) -> Duration {
+    self.config.flush_interval
+  }
+
+  fn send_event_batch(&mut self, batch: BatchEventPayload) {
+    ClientWorker::send_event_batch(self, batch);
+  }
+
+  /// Batches that fail once the session is ending are dropped, there is no later flush to retry them in.
+  fn hold_back(&mut self, events: Vec<EventPayload>) {
+    if self.ending {
+      if self.log_level <= IndigaugeLogLevel::Error {
+        error!(message = "Dropping event batch that failed while ending the session", count = events.len());
+      }
+      return;
+    }
+
+    self.requeue(events, event_seq_generation());
+  }
+
+  fn session_expired(&mut self, events: Vec<EventPayload>) {
+    let generation = event_seq_generation();
+    if self.continue_session() {
+      // The rejected events are numbered before the events buffered in the meantime.
+      self.requeue(events, generation);
+      self.buffered_events.resequence();
+    } else {
+      self.transport.rate_limit(None, self.config.flush_interval);
+      self.hold_back(events);
+    }
+  }
+}
@@ -2,6 +2,8 @@ use std::{env, time::Duration};
 
 use bevy::prelude::*;
 
+use crate::transport::{IndigaugeCompression, IndigaugeWireFormat};
+
 #[derive(Resource, Clone)]
 pub struct IndigaugeConfig {
   pub(crate) api_base: String,
@@ -12,6 +14,26 @@ pub struct IndigaugeConfig {
   pub(crate) flush_interval: Duration,
   pub(crate) max_queue: usize,
   pub(crate) request_timeout: Duration,
+  pub(crate) stamp_game_time: bool,
+  pub(crate) priority_event_types: Vec<String>,
+  pub(crate) priority_batch_size: usize,
+  pub(crate) priority_flush_interval: Duration,
+  pub(crate) compression: IndigaugeCompression,
+  pub(crate) compression_threshold: usize,
+  pub(crate) wire_format: IndigaugeWireFormat,
+  pub(crate) max_batch_bytes: usize,
+  pub(crate) max_event_metadata_bytes: usize,
+  pub(crate) oversized_metadata: OversizedMetadataPolicy,
+  pub(crate) session_start_retry: SessionStartRetry,
+  pub(crate) offline_first: bool,
+  pub(crate) shutdown_timeout: Duration,
+  pub(crate) idle_threshold: Duration,
+  pub(crate) idle_timeout: Option<Duration>,
+  pub(crate) heartbeat_interval: Duration,
+  /// Directory for files kept between launches, such as stored sessions and the remote config. Defaults to the
+  /// game's folder in the preference directory.
+  #[cfg(not(target_family = "wasm"))]
+  pub(crate) storage_dir: Option<std::path::PathBuf>,
 }
 
 impl IndigaugeConfig {
@@ -25,10 +47,91 @@ impl IndigaugeConfig {
       flush_interval: Duration::from_secs(10),
       max_queue: 10_000,
       request_timeout: Duration::from_secs(10),
+      stamp_game_time: false,
+      priority_event_types: Vec::new(),
+      priority_batch_size: 8,
+      priority_flush_interval: Duration::from_secs(1),
+      compression: IndigaugeCompression::default(),
+      compression_threshold: 1024,
+      wire_format: IndigaugeWireFormat::default(),
+      max_batch_bytes: 512 * 1024,
+      max_event_metadata_bytes: 16 * 1024,
+      oversized_metadata: OversizedMetadataPolicy::default(),
+      session_start_retry: SessionStartRetry::default(),
+      offline_first: false,
+      shutdown_timeout: Duration::from_secs(3),
+      idle_threshold: Duration::from_secs(5 * 60),
+      idle_timeout: None,
+      heartbeat_interval: Duration::from_secs(30),
+      #[cfg(not(target_family = "wasm"))]
+      storage_dir: None,
+    }
+  }
+
+  /// Directory for files kept between launches, see [`IndigaugeConfig::storage_dir`].
+  #[cfg(not(target_family = "wasm"))]
+  pub(crate) fn storage_dir(&self) -> Option<std::path::PathBuf> {
+    self
+      .storage_dir
+      .clone()
+      .or_else(|| dirs::preference_dir().map(|dir| dir.join(&self.game_name)))
+  }
+}
+
+/// How failed session starts are retried. The delay between attempts doubles after every failure, up to `max_backoff`.
+///
+/// Network errors, server errors and rate limiting are retried. A session start rejected by the server, e.g.
+/// because of an invalid public key, is not.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SessionStartRetry {
+  /// Maximum number of attempts, including the first one. `None` retries until the session is started.
+  pub max_attempts: Option<u32>,
+  /// Delay before the first retry.
+  pub initial_backoff: Duration,
+  /// Upper bound for the delay between attempts.
+  pub max_backoff: Duration,
+}
+
+impl Default for SessionStartRetry {
+  fn default() -> Self {
+    Self {
+      max_attempts: None,
+      initial_backoff: Duration::from_secs(2),
+      max_backoff: Duration::from_secs(5 * 60),
     }
   }
 }
 
+impl SessionStartRetry {
+  /// Never retry failed session starts.
+  pub fn disabled() -> Self {
+    Self {
+      max_attempts: Some(1),
+      ..Default::default()
+    }
+  }
+
+  pub(crate) fn allows_retry(&self, attempts: u32) -> bool {
+    self.max_attempts.is_none_or(|max_attempts| attempts < max_attempts)
+  }
+
+  /// Delay before the next attempt, after `attempts` failed attempts.
+  pub(crate) fn backoff(&self, attempts: u32) -> Duration {
+    let factor = 2u32.saturating_pow(attempts.saturating_sub(1));
+    self.initial_backoff.saturating_mul(factor).min(self.max_backoff)
+  }
+}
+
+/// What to do with events whose serialized metadata exceeds the configured limit.
+#[derive(PartialEq, Default, Clone, Copy, Debug)]
+pub enum OversizedMetadataPolicy {
+  /// Replace the metadata with `{"truncated": true, "originalBytes": <size>}` and send the event.
+  #[default]
+  Truncate,
+  /// Drop the event.
+  Reject,
+}
+
 #[derive(Resource, PartialEq, PartialOrd, Clone)]
 pub enum IndigaugeLogLevel {
   #[cfg(feature = "tracing")]
@@ -80,3 +183,25 @@ pub enum IndigaugeMode {
   /// Disabled mode does not send any data to the Indigauge API.
   Disabled,
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn session_start_backoff_doubles_up_to_the_limit() {
+    let retry = SessionStartRetry {
+      max_attempts: Some(4),
+      initial_backoff: Duration::from_secs(2),
+      max_backoff: Duration::from_secs(10),
+    };
+
+    assert_eq!(retry.backoff(1), Duration::from_secs(2));
+    assert_eq!(retry.backoff(2), Duration::from_secs(4));
+    assert_eq!(retry.backoff(3), Duration::from_secs(8));
+    assert_eq!(retry.backoff(4), Duration::from_secs(10));
+    assert!(retry.allows_retry(3));
+    assert!(!retry.allows_retry(4));
+    assert!(!SessionStartRetry::disabled().allows_retry(1));
+  }
+}
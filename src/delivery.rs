@@ -0,0 +1,209 @@
+use std::time::Duration;
+
+use bevy_mod_reqwest::reqwest::StatusCode;
+use tracing::{error, info, warn};
+
+use crate::{
+  api_types::{BatchEventPayload, EventPayload},
+  config::IndigaugeLogLevel,
+  transport::{BodyEncoding, IndigaugeWireFormat, TransportState},
+};
+
+/// What came back for an event batch request.
+pub(crate) enum BatchResponse<'a> {
+  Status {
+    status: StatusCode,
+    /// Value of the `Retry-After` header.
+    retry_after: Option<&'a str>,
+  },
+  /// No response arrived, e.g. because the server was unreachable.
+  Failed(&'a dyn std::fmt::Display),
+}
+
+/// How event batches are sent, by the Bevy plugin or by the standalone client. Both handle the response to a batch
+/// with [`handle_batch_response`], so they split, fall back and retry batches the same way.
+pub(crate) trait BatchDelivery {
+  fn log_level(&self) -> &IndigaugeLogLevel;
+
+  fn transport(&mut self) -> &mut TransportState;
+
+  /// How long batches are held back after a failure if the server didn't say how long to wait.
+  fn retry_interval(&self) -> Duration;
+
+  /// Encodes the batch with the current transport settings and sends it.
+  fn send_event_batch(&mut self, batch: BatchEventPayload);
+
+  /// Buffers the events of a batch that was not accepted again, to be sent once batches are no longer held back.
+  fn hold_back(&mut self, events: Vec<EventPayload>);
+
+  /// The session token was rejected. The events are sent again once a continuation session is started.
+  fn session_expired(&mut self, events: Vec<EventPayload>);
+}
+
+/// Handles the response to an event batch that was sent with `encoding`.
+///
+/// Rate limited batches, server errors and batches that got no response are held back and retried. Batches that are
+/// too large are split, and batches with an encoding the server rejected are sent again with a fallback encoding.
+pub(crate) fn handle_batch_response<D>(
+  delivery: &mut D,
+  mut batch: BatchEventPayload,
+  encoding: BodyEncoding,
+  response: BatchResponse,
+) where
+  D: BatchDelivery,
+{
+  let log_level = delivery.log_level().clone();
+  let retry_interval = delivery.retry_interval();
+
+  let (status, retry_after) = match response {
+    BatchResponse::Status { status, retry_after } => (status, retry_after),
+    BatchResponse::Failed(error) => {
+      if log_level <= IndigaugeLogLevel::Warn {
+        warn!(message = "Failed to send event batch, retrying later", %error, count = batch.events.len());
+      }
+      delivery.transport().rate_limit(None, retry_interval);
+      delivery.hold_back(batch.events);
+      return;
+    },
+  };
+
+  if status.is_success() {
+    if log_level <= IndigaugeLogLevel::Info {
+      info!(message = "Event batch sent successfully");
+    }
+  } else if status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error() {
+    if log_level <= IndigaugeLogLevel::Warn {
+      warn!(message = "Event batch not accepted, retrying later", %status, count = batch.events.len());
+    }
+    delivery.transport().rate_limit(retry_after, retry_interval);
+    delivery.hold_back(batch.events);
+  } else if status == StatusCode::UNAUTHORIZED {
+    delivery.session_expired(batch.events);
+  } else if status == StatusCode::PAYLOAD_TOO_LARGE {
+    if batch.events.len() > 1 {
+      if log_level <= IndigaugeLogLevel::Warn {
+        warn!(message = "Event batch too large, splitting and retrying", count = batch.events.len());
+      }
+      let second_half = batch.events.split_off(batch.events.len() / 2);
+      delivery.send_event_batch(batch);
+      delivery.send_event_batch(BatchEventPayload::new(second_half));
+    } else if log_level <= IndigaugeLogLevel::Error {
+      let event_type = batch.events.first().map(|event| event.event_type.as_ref());
+      error!(message = "Event rejected by server as too large", ?event_type);
+    }
+  } else if status == StatusCode::UNSUPPORTED_MEDIA_TYPE
+    && (encoding.wire_format != IndigaugeWireFormat::Json || encoding.compressed)
+  {
+    // Fall back one step at a time: first to JSON, then to an uncompressed body.
+    if encoding.wire_format != IndigaugeWireFormat::Json {
+      if log_level <= IndigaugeLogLevel::Warn {
+        warn!(message = "Binary event batch rejected, resending as JSON", %status);
+      }
+      delivery.transport().wire_format_rejected = true;
+    } else {
+      if log_level <= IndigaugeLogLevel::Warn {
+        warn!(message = "Compressed event batch rejected, resending uncompressed", %status);
+      }
+      delivery.transport().compression_rejected = true;
+    }
+    delivery.send_event_batch(batch);
+  } else if log_level <= IndigaugeLogLevel::Error {
+    error!(message = "Failed to send event batch", %status);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[derive(Default)]
+  struct RecordingDelivery {
+    transport: TransportState,
+    sent: Vec<usize>,
+    held_back: Vec<usize>,
+    expired: Vec<usize>,
+  }
+
+  impl BatchDelivery for RecordingDelivery {
+    fn log_level(&self) -> &IndigaugeLogLevel {
+      &IndigaugeLogLevel::Error
+    }
+
+    fn transport(&mut self) -> &mut TransportState {
+      &mut self.transport
+    }
+
+    fn retry_interval(&self) -> Duration {
+      Duration::from_secs(10)
+    }
+
+    fn send_event_batch(&mut self, batch: BatchEventPayload) {
+      self.sent.push(batch.events.len());
+    }
+
+    fn hold_back(&mut self, events: Vec<EventPayload>) {
+      self.held_back.push(events.len());
+    }
+
+    fn session_expired(&mut self, events: Vec<EventPayload>) {
+      self.expired.push(events.len());
+    }
+  }
+
+  fn batch(count: usize) -> BatchEventPayload {
+    let events = (0..count)
+      .map(|seq| EventPayload {
+        event_type: "ui.click".into(),
+        metadata: None,
+        level: "info",
+        elapsed_ms: 0,
+        timestamp: 0,
+        seq: seq as u64,
+        idempotency_key: None,
+        context: None,
+        game_time: None,
+      })
+      .collect();
+    BatchEventPayload::new(events)
+  }
+
+  fn respond(delivery: &mut RecordingDelivery, count: usize, response: BatchResponse) {
+    let encoding = BodyEncoding {
+      wire_format: IndigaugeWireFormat::Json,
+      compressed: false,
+    };
+    handle_batch_response(delivery, batch(count), encoding, response);
+  }
+
+  #[test]
+  fn failed_batches_are_retried_split_or_expired_by_status() {
+    let mut delivery = RecordingDelivery::default();
+
+    let status = |status| BatchResponse::Status {
+      status,
+      retry_after: None,
+    };
+    respond(&mut delivery, 4, status(StatusCode::OK));
+    respond(&mut delivery, 4, status(StatusCode::BAD_REQUEST));
+    assert!(delivery.sent.is_empty() && delivery.held_back.is_empty());
+
+    respond(&mut delivery, 4, status(StatusCode::PAYLOAD_TOO_LARGE));
+    assert_eq!(delivery.sent, vec![2, 2]);
+
+    respond(&mut delivery, 3, status(StatusCode::UNAUTHORIZED));
+    assert_eq!(delivery.expired, vec![3]);
+
+    respond(&mut delivery, 1, status(StatusCode::SERVICE_UNAVAILABLE));
+    respond(&mut delivery, 2, BatchResponse::Failed(&"connection refused"));
+    respond(
+      &mut delivery,
+      3,
+      BatchResponse::Status {
+        status: StatusCode::TOO_MANY_REQUESTS,
+        retry_after: Some("120"),
+      },
+    );
+    assert_eq!(delivery.held_back, vec![1, 2, 3]);
+    assert!(delivery.transport.is_rate_limited());
+  }
+}
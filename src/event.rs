@@ -3,11 +3,16 @@ use std::time::Duration;
 use bevy::{prelude::*, time::common_conditions::on_timer};
 
 use crate::{
-  event::{resources::BufferedEvents, systems::*},
-  session::resources::SessionApiKey,
+  event::{
+    resources::{BufferedEvents, EventQueueReceiver, FixedTicks},
+    systems::*,
+  },
+  session::{conditions::ig_session_ended, resources::SessionApiKey},
 };
 
+pub(crate) mod metadata;
 pub(crate) mod resources;
+pub(crate) mod sinks;
 mod systems;
 pub(crate) mod utils;
 
@@ -23,14 +28,24 @@ impl EventsPlugin {
 
 impl Plugin for EventsPlugin {
   fn build(&self, app: &mut App) {
+    app.init_resource::<FixedTicks>().add_systems(FixedFirst, count_fixed_ticks);
+    app.add_systems(
+      Update,
+      (
+        handle_queued_events.run_if(resource_exists::<EventQueueReceiver>),
+        flush_event_sinks,
+      ),
+    );
+    // Events captured before the session is established stay buffered until it is. Once it has ended, they are no
+    // longer sent.
     app.add_systems(
       Update,
       (
-        handle_queued_events,
         maybe_flush_events.run_if(resource_changed::<BufferedEvents>),
+        maybe_flush_priority_events,
         flush_events.run_if(on_timer(self.flush_interval)),
       )
-        .run_if(resource_exists::<SessionApiKey>),
+        .run_if(resource_exists::<SessionApiKey>.and(not(ig_session_ended()))),
     );
   }
 }
@@ -40,7 +55,41 @@ pub mod macros {
   macro_rules! enqueue_ig_event {
     ($level: ident, $etype:expr, $metadata:expr) => {
       const _VALID: &str = $crate::prelude::validate_event_type_compile_time($etype);
-      let _ = $crate::prelude::enqueue(stringify!($level), $etype, $metadata, file!(), line!(), module_path!());
+      let _ = $crate::prelude::enqueue_event(stringify!($level), $etype, $metadata, file!(), line!(), module_path!());
+    };
+  }
+
+  /// Captures event metadata as an inline key/value vector. Numbers and booleans are stored without
+  /// allocating, strings are copied and other values are converted to JSON. Keys are string literals, which are not
+  /// copied, or any expression that converts into a `String`.
+  #[doc(hidden)]
+  #[macro_export]
+  macro_rules! ig_metadata {
+    ({ $($entries:tt)* }) => {{
+      #[allow(unused_imports)]
+      use $crate::__macro_support::{CaptureInline as _, CaptureSerialize as _};
+      #[allow(unused_mut)]
+      let mut metadata = $crate::__macro_support::InlineMetadata::new();
+      $crate::ig_metadata!(@entries metadata () $($entries)*);
+      metadata
+    }};
+    // An `expr` can't be followed by a `:`, so key tokens are collected up to the `:` in front of the value.
+    (@entries $metadata:ident ()) => {};
+    (@entries $metadata:ident ($($key:tt)+) : $value:expr $(, $($rest:tt)*)?) => {
+      $metadata.push((
+        $crate::ig_metadata!(@key $($key)+),
+        (&&$crate::__macro_support::MetadataCapture(&$value)).capture_metadata(),
+      ));
+      $crate::ig_metadata!(@entries $metadata () $($($rest)*)?);
+    };
+    (@entries $metadata:ident ($($key:tt)*) $next:tt $($rest:tt)*) => {
+      $crate::ig_metadata!(@entries $metadata ($($key)* $next) $($rest)*);
+    };
+    (@key $key:literal) => {
+      $crate::__macro_support::MetadataKey::Borrowed($key)
+    };
+    (@key $($key:tt)+) => {
+      $crate::__macro_support::MetadataKey::Owned(::std::string::String::from($($key)+))
     };
   }
 
@@ -50,9 +99,9 @@ pub mod macros {
     ($level:ident, $etype:expr $(,)?) => {{
       $crate::enqueue_ig_event!($level, $etype, None);
     }};
-    ($level:ident, $etype:expr $(, { $($key:tt : $value:expr),* $(,)? })? ) => {{
-      let meta = serde_json::json!({ $($($key : $value),*)? });
-      $crate::enqueue_ig_event!($level, $etype, Some(meta));
+    ($level:ident, $etype:expr $(, { $($metadata:tt)* })? ) => {{
+      let meta = $crate::ig_metadata!({ $($($metadata)*)? });
+      $crate::enqueue_ig_event!($level, $etype, Some($crate::__macro_support::EventMetadata::Inline(meta)));
     }};
   }
 
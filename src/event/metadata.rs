@@ -0,0 +1,226 @@
+use std::borrow::Cow;
+
+use serde::{Serialize, Serializer, ser::SerializeMap};
+use smallvec::SmallVec;
+
+/// Key of an inline metadata entry. String literal keys are borrowed.
+pub type MetadataKey = Cow<'static, str>;
+
+/// Key/value metadata captured inline by the event macros. Up to four entries are stored without allocating.
+pub type InlineMetadata = SmallVec<[(MetadataKey, MetadataValue); 4]>;
+
+/// Metadata attached to an event.
+///
+/// Metadata captured by the `ig_*!` macros is kept as an [`InlineMetadata`] vector instead of being converted to a
+/// JSON value where the event is logged. It is serialized when the event is buffered, to measure its size, and when
+/// the event batch is sent.
+#[derive(Clone, Debug, PartialEq)]
+pub enum EventMetadata {
+  Json(serde_json::Value),
+  Inline(InlineMetadata),
+}
+
+impl EventMetadata {
+  /// Converts the metadata into a JSON value.
+  pub fn to_value(&self) -> serde_json::Value {
+    match self {
+      EventMetadata::Json(value) => value.clone(),
+      EventMetadata::Inline(_) => serde_json::to_value(self).unwrap_or_default(),
+    }
+  }
+}
+
+impl From<serde_json::Value> for EventMetadata {
+  fn from(value: serde_json::Value) -> Self {
+    EventMetadata::Json(value)
+  }
+}
+
+impl Serialize for EventMetadata {
+  fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+  where
+    S: Serializer,
+  {
+    match self {
+      EventMetadata::Json(value) => value.serialize(serializer),
+      EventMetadata::Inline(entries) => {
+        let mut map = serializer.serialize_map(Some(entries.len()))?;
+        for (key, value) in entries {
+          map.serialize_entry(key, value)?;
+        }
+        map.end()
+      },
+    }
+  }
+}
+
+/// A single inline metadata value. Numbers and booleans are stored without allocating, strings are copied.
+#[derive(Clone, Debug, PartialEq)]
+pub enum MetadataValue {
+  Bool(bool),
+  I64(i64),
+  U64(u64),
+  F64(f64),
+  String(String),
+  Json(serde_json::Value),
+}
+
+impl Serialize for MetadataValue {
+  fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+  where
+    S: Serializer,
+  {
+    match self {
+      MetadataValue::Bool(value) => serializer.serialize_bool(*value),
+      MetadataValue::I64(value) => serializer.serialize_i64(*value),
+      MetadataValue::U64(value) => serializer.serialize_u64(*value),
+      MetadataValue::F64(value) => serializer.serialize_f64(*value),
+      MetadataValue::String(value) => serializer.serialize_str(value),
+      MetadataValue::Json(value) => value.serialize(serializer),
+    }
+  }
+}
+
+macro_rules! impl_metadata_value_from {
+  ($variant:ident($target:ty): $($source:ty),*) => {
+    $(
+      impl From<$source> for MetadataValue {
+        fn from(value: $source) -> Self {
+          MetadataValue::$variant(value as $target)
+        }
+      }
+
+      impl CaptureInline for &MetadataCapture<'_, $source> {
+        fn capture_metadata(&self) -> MetadataValue {
+          MetadataValue::from(*self.0)
+        }
+      }
+    )*
+  };
+}
+
+impl_metadata_value_from!(I64(i64): i8, i16, i32, i64, isize);
+impl_metadata_value_from!(U64(u64): u8, u16, u32, u64, usize);
+impl_metadata_value_from!(F64(f64): f32, f64);
+
+impl From<bool> for MetadataValue {
+  fn from(value: bool) -> Self {
+    MetadataValue::Bool(value)
+  }
+}
+
+impl From<String> for MetadataValue {
+  fn from(value: String) -> Self {
+    MetadataValue::String(value)
+  }
+}
+
+macro_rules! impl_capture_string {
+  ($($source:ty),*) => {
+    $(
+      impl CaptureInline for &MetadataCapture<'_, $source> {
+        fn capture_metadata(&self) -> MetadataValue {
+          MetadataValue::String(self.0.to_string())
+        }
+      }
+    )*
+  };
+}
+
+impl_capture_string!(str, &str, String, &String, Cow<'_, str>);
+
+impl From<serde_json::Value> for MetadataValue {
+  fn from(value: serde_json::Value) -> Self {
+    MetadataValue::Json(value)
+  }
+}
+
+/// Wraps a metadata value in the event macros, so that the cheapest way to capture it can be picked.
+///
+/// Numbers, booleans and strings resolve to [`CaptureInline`] and are copied. Every other serializable value resolves
+/// to [`CaptureSerialize`] and is converted to JSON, like before.
+#[doc(hidden)]
+pub struct MetadataCapture<'a, T: ?Sized>(pub &'a T);
+
+#[doc(hidden)]
+pub trait CaptureInline {
+  fn capture_metadata(&self) -> MetadataValue;
+}
+
+#[doc(hidden)]
+pub trait CaptureSerialize {
+  fn capture_metadata(&self) -> MetadataValue;
+}
+
+impl CaptureInline for &MetadataCapture<'_, bool> {
+  fn capture_metadata(&self) -> MetadataValue {
+    MetadataValue::Bool(*self.0)
+  }
+}
+
+impl<T> CaptureSerialize for MetadataCapture<'_, T>
+where
+  T: Serialize + ?Sized,
+{
+  fn capture_metadata(&self) -> MetadataValue {
+    MetadataValue::Json(serde_json::to_value(self.0).unwrap_or_default())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serde_json::json;
+
+  #[test]
+  fn inline_metadata_serializes_like_json() {
+    let position = vec![1, 2];
+    let name = String::from("player");
+    let metadata = EventMetadata::Inline(crate::ig_metadata!({
+      "x": 128,
+      "y": 256.5_f32,
+      "alive": true,
+      "name": name,
+      "position": position,
+    }));
+
+    assert_eq!(
+      metadata.to_value(),
+      json!({ "x": 128, "y": 256.5, "alive": true, "name": "player", "position": [1, 2] })
+    );
+  }
+
+  #[test]
+  fn keys_can_be_expressions_and_strings_are_captured_as_strings() {
+    const BUTTON: &str = "button";
+    let prefix = String::from("slot");
+    let label = "play";
+    let entries = crate::ig_metadata!({ BUTTON: label, format!("{prefix}.1"): String::from("sword"), "x": 1 });
+
+    assert_eq!(
+      entries.into_vec(),
+      vec![
+        (MetadataKey::from("button"), MetadataValue::String("play".to_string())),
+        (MetadataKey::from("slot.1"), MetadataValue::String("sword".to_string())),
+        (MetadataKey::Borrowed("x"), MetadataValue::I64(1)),
+      ]
+    );
+  }
+
+  #[test]
+  fn numbers_and_booleans_are_captured_inline() {
+    let frame = 42_u32;
+    let entries = crate::ig_metadata!({ "frame": frame, "x": -3, "speed": 1.5, "alive": false });
+
+    assert!(!entries.spilled());
+    assert_eq!(
+      entries.iter().map(|(_, value)| value.clone()).collect::<Vec<_>>(),
+      vec![
+        MetadataValue::U64(42),
+        MetadataValue::I64(-3),
+        MetadataValue::F64(1.5),
+        MetadataValue::Bool(false)
+      ]
+    );
+  }
+}
@@ -1,24 +1,99 @@
-use std::ops::{Deref, DerefMut};
+use std::{
+  ops::{Deref, DerefMut},
+  time::Instant,
+};
 
 use bevy::prelude::*;
 use crossbeam_channel::Receiver;
 
-use crate::api_types::EventPayload;
+use crate::{
+  api_types::{EventGameTime, EventPayload},
+  config::{IndigaugeConfig, IndigaugeLogLevel, OversizedMetadataPolicy},
+  event::metadata::EventMetadata,
+  session::{event_seq_generation, next_event_seq},
+};
+
+/// Counts serialized bytes without buffering them.
+struct ByteCounter(usize);
+
+impl std::io::Write for ByteCounter {
+  fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+    self.0 += buf.len();
+    Ok(buf.len())
+  }
+
+  fn flush(&mut self) -> std::io::Result<()> {
+    Ok(())
+  }
+}
+
+/// Serialized JSON size of the value in bytes, measured without allocating.
+fn serialized_len<S>(value: &S) -> usize
+where
+  S: serde::Serialize,
+{
+  let mut counter = ByteCounter(0);
+  serde_json::to_writer(&mut counter, value).map(|_| counter.0).unwrap_or_default()
+}
 
 #[derive(Clone, Debug)]
 pub struct QueuedEvent {
   payload: EventPayload,
+  /// Serialized size of the payload in bytes. Measured when the event is buffered.
+  size: usize,
+  /// Generation of the session the sequence number belongs to.
+  generation: u64,
 }
 
 impl QueuedEvent {
-  pub fn new(payload: EventPayload) -> Self {
-    Self { payload }
+  pub fn new(payload: EventPayload, generation: u64) -> Self {
+    Self {
+      payload,
+      size: 0,
+      generation,
+    }
+  }
+
+  pub fn payload(&self) -> &EventPayload {
+    &self.payload
+  }
+
+  pub fn event_type(&self) -> &str {
+    &self.payload.event_type
+  }
+
+  pub fn size(&self) -> usize {
+    self.size
+  }
+
+  /// Numbers the event as the next event of the current session.
+  pub(crate) fn resequence(&mut self) {
+    (self.generation, self.payload.seq) = next_event_seq();
+  }
+
+  pub(crate) fn measure(&mut self) {
+    self.size = serialized_len(&self.payload);
+  }
+
+  pub(crate) fn metadata_size(&self) -> usize {
+    self.payload.metadata.as_ref().map(serialized_len).unwrap_or_default()
+  }
+
+  /// Replaces the metadata with a marker noting that it was truncated.
+  pub(crate) fn truncate_metadata(&mut self, original_size: usize) {
+    self.payload.metadata = Some(EventMetadata::Json(
+      serde_json::json!({ "truncated": true, "originalBytes": original_size }),
+    ));
   }
 
   pub fn into_inner(self) -> EventPayload {
     self.payload
   }
 
+  pub fn stamp_game_time(&mut self, game_time: EventGameTime) {
+    self.payload.game_time = Some(game_time);
+  }
+
   pub fn validate(&self) -> Result<(), String> {
     // Add validation logic here
     let (ns, t) = self.payload.event_type.split_once('.').ok_or("Invalid event type")?;
@@ -28,8 +103,62 @@ impl QueuedEvent {
 
     Ok(())
   }
+
+  /// Validates the event, applies the metadata size policy and measures it. Returns false if the event
+  /// should be dropped.
+  pub(crate) fn prepare(&mut self, config: &IndigaugeConfig, log_level: &IndigaugeLogLevel) -> bool {
+    if let Err(error) = self.validate() {
+      if *log_level <= IndigaugeLogLevel::Error {
+        error!(message = "Invalid event", ?error);
+      }
+      return false;
+    }
+
+    let metadata_size = self.metadata_size();
+    if metadata_size > config.max_event_metadata_bytes {
+      match config.oversized_metadata {
+        OversizedMetadataPolicy::Reject => {
+          if *log_level <= IndigaugeLogLevel::Warn {
+            warn!(
+              message = "Event metadata exceeds limit, event rejected",
+              event_type = self.event_type(),
+              metadata_size
+            );
+          }
+          return false;
+        },
+        OversizedMetadataPolicy::Truncate => {
+          if *log_level <= IndigaugeLogLevel::Warn {
+            warn!(
+              message = "Event metadata exceeds limit, metadata truncated",
+              event_type = self.event_type(),
+              metadata_size
+            );
+          }
+          self.truncate_metadata(metadata_size);
+        },
+      }
+    }
+
+    self.measure();
+    true
+  }
+
+  /// Error events, as well as events with a configured priority event type, skip the regular queue.
+  pub fn is_priority(&self, config: &IndigaugeConfig) -> bool {
+    self.payload.level == "error"
+      || config
+        .priority_event_types
+        .iter()
+        .any(|event_type| *event_type == self.payload.event_type)
+  }
 }
 
+/// Number of times the fixed timestep schedules have run. Unlike dividing the elapsed `Time<Fixed>` by the timestep,
+/// it stays correct when the timestep changes.
+#[derive(Resource, Default)]
+pub(crate) struct FixedTicks(pub u64);
+
 #[derive(Resource)]
 pub struct EventQueueReceiver {
   rx: Receiver<QueuedEvent>,
@@ -57,5 +186,207 @@ impl DerefMut for EventQueueReceiver {
 
 #[derive(Resource, Default)]
 pub struct BufferedEvents {
-  pub events: Vec<QueuedEvent>,
+  events: Vec<QueuedEvent>,
+  priority_events: Vec<QueuedEvent>,
+  /// Total serialized size of `events`, kept up to date by `push` and the `take_*` methods.
+  events_size: usize,
+  pub(crate) last_priority_flush: Option<Instant>,
+}
+
+impl BufferedEvents {
+  pub fn events(&self) -> &[QueuedEvent] {
+    &self.events
+  }
+
+  pub fn priority_events(&self) -> &[QueuedEvent] {
+    &self.priority_events
+  }
+
+  /// Whether no regular or priority events are buffered.
+  pub fn is_empty(&self) -> bool {
+    self.events.is_empty() && self.priority_events.is_empty()
+  }
+
+  /// Total serialized size of the regular events in bytes.
+  pub fn events_size(&self) -> usize {
+    self.events_size
+  }
+
+  /// Adds a prepared event to the priority or regular queue. Drops the event if `max_queue` events are already
+  /// buffered, e.g. while the session is still being started.
+  pub(crate) fn push(&mut self, mut event: QueuedEvent, config: &IndigaugeConfig) {
+    if self.events.len() + self.priority_events.len() >= config.max_queue {
+      return;
+    }
+
+    if event.generation != event_seq_generation() {
+      event.resequence();
+    }
+
+    if event.is_priority(config) {
+      self.priority_events.push(event);
+    } else {
+      self.events_size += event.size();
+      self.events.push(event);
+    }
+  }
+
+  /// Renumbers the events that were numbered for an earlier session, in their original order.
+  pub(crate) fn resequence(&mut self) {
+    let generation = event_seq_generation();
+    let mut stale = self
+      .priority_events
+      .iter_mut()
+      .chain(self.events.iter_mut())
+      .filter(|event| event.generation != generation)
+      .collect::<Vec<_>>();
+
+    stale.sort_by_key(|event| event.payload.seq);
+    for event in stale {
+      event.resequence();
+    }
+  }
+
+  /// Whether the regular queue holds a full batch, by count or by size.
+  pub(crate) fn is_batch_full(&self, config: &IndigaugeConfig) -> bool {
+    self.events.len() >= config.batch_size || self.events_size() >= config.max_batch_bytes
+  }
+
+  /// Whether priority events are waiting and the priority flush interval has elapsed.
+  pub(crate) fn is_priority_flush_due(&self, config: &IndigaugeConfig) -> bool {
+    !self.priority_events.is_empty()
+      && self
+        .last_priority_flush
+        .is_none_or(|last_flush| last_flush.elapsed() >= config.priority_flush_interval)
+  }
+
+  /// Takes up to `batch_size` events. Priority events are always taken ahead of regular events.
+  pub(crate) fn take_batch(&mut self, config: &IndigaugeConfig) -> Vec<EventPayload> {
+    let (batch_size, max_batch_bytes) = (config.batch_size, config.max_batch_bytes);
+    let (priority_count, priority_bytes) = batch_len(&self.priority_events, batch_size, max_batch_bytes, true);
+    let (event_count, event_bytes) = batch_len(
+      &self.events,
+      batch_size - priority_count,
+      max_batch_bytes.saturating_sub(priority_bytes),
+      priority_count == 0,
+    );
+
+    if priority_count > 0 {
+      self.last_priority_flush = Some(Instant::now());
+    }
+    self.events_size -= event_bytes;
+
+    self
+      .priority_events
+      .drain(..priority_count)
+      .chain(self.events.drain(..event_count))
+      .map(QueuedEvent::into_inner)
+      .collect()
+  }
+
+  /// Takes every buffered event, priority events first.
+  pub(crate) fn take_all(&mut self) -> Vec<EventPayload> {
+    self.events_size = 0;
+    self
+      .priority_events
+      .drain(..)
+      .chain(self.events.drain(..))
+      .map(QueuedEvent::into_inner)
+      .collect()
+  }
+
+  /// Takes up to `priority_batch_size` priority events without waiting for regular events.
+  pub(crate) fn take_priority_batch(&mut self, config: &IndigaugeConfig) -> Vec<EventPayload> {
+    let (priority_count, _) =
+      batch_len(&self.priority_events, config.priority_batch_size, config.max_batch_bytes, true);
+
+    self.last_priority_flush = Some(Instant::now());
+
+    self
+      .priority_events
+      .drain(..priority_count)
+      .map(QueuedEvent::into_inner)
+      .collect()
+  }
+}
+
+/// Returns how many of the leading events fit in a batch of `max_count` events and `max_bytes` bytes,
+/// and their total size. If `force_first` is set, the first event is included even if it exceeds `max_bytes`.
+pub(crate) fn batch_len(
+  events: &[QueuedEvent],
+  max_count: usize,
+  max_bytes: usize,
+  force_first: bool,
+) -> (usize, usize) {
+  let mut count = 0;
+  let mut bytes = 0;
+
+  for event in events.iter().take(max_count) {
+    if bytes + event.size() > max_bytes && !(force_first && count == 0) {
+      break;
+    }
+    count += 1;
+    bytes += event.size();
+  }
+
+  (count, bytes)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn sized_event(size: usize) -> QueuedEvent {
+    QueuedEvent {
+      payload: EventPayload {
+        event_type: "test.event".into(),
+        metadata: None,
+        level: "info",
+        elapsed_ms: 0,
+        timestamp: 0,
+        seq: 0,
+        idempotency_key: None,
+        context: None,
+        game_time: None,
+      },
+      size,
+      generation: 0,
+    }
+  }
+
+  #[test]
+  fn batches_are_cut_by_count_and_bytes() {
+    let events = [sized_event(100), sized_event(100), sized_event(100), sized_event(100)];
+
+    assert_eq!(batch_len(&events, 2, 1000, false), (2, 200));
+    assert_eq!(batch_len(&events, 10, 250, false), (2, 200));
+    assert_eq!(batch_len(&events, 10, 50, false), (0, 0));
+  }
+
+  #[test]
+  fn oversized_first_event_is_included_when_forced() {
+    let events = [sized_event(500), sized_event(100)];
+
+    assert_eq!(batch_len(&events, 10, 200, true), (1, 500));
+    assert_eq!(batch_len(&events, 10, 200, false), (0, 0));
+  }
+
+  #[test]
+  fn buffered_size_follows_pushed_and_taken_events() {
+    let mut config = IndigaugeConfig::new("game", "public-key", "1.0.0");
+    config.batch_size = 2;
+    let mut buffered_events = BufferedEvents::default();
+    for size in [100, 200, 300] {
+      buffered_events.push(sized_event(size), &config);
+    }
+    assert_eq!(buffered_events.events_size(), 600);
+
+    buffered_events.take_batch(&config);
+    assert_eq!(buffered_events.events_size(), 300);
+
+    buffered_events.push(sized_event(50), &config);
+    buffered_events.take_all();
+    assert_eq!(buffered_events.events_size(), 0);
+    assert!(buffered_events.is_empty());
+  }
 }
@@ -0,0 +1,378 @@
+use std::{
+  collections::VecDeque,
+  sync::{Arc, Mutex},
+  time::{Duration, Instant},
+};
+
+use bevy::{prelude::*, tasks::IoTaskPool};
+
+use crate::{api_types::EventPayload, config::IndigaugeLogLevel};
+
+/// A destination that receives event batches in addition to the Indigauge API.
+///
+/// Sinks are registered with [`IndigaugePlugin::add_sink`](crate::prelude::IndigaugePlugin::add_sink) and are
+/// fed from the same event stream as the Indigauge API, so an event is enqueued once but delivered to every
+/// matching sink. Batches are delivered on the IO task pool, so `send` may block. A sink receives its batches one at a
+/// time and in order.
+///
+/// # Example
+/// ```
+/// use bevy_mod_indigauge::prelude::*;
+///
+/// struct StdoutSink;
+///
+/// impl EventSink for StdoutSink {
+///   fn send(&mut self, events: &[EventPayload]) {
+///     for event in events {
+///       println!("{} {}", event.level, event.event_type);
+///     }
+///   }
+/// }
+///
+/// let plugin = IndigaugePlugin::<EmptySessionMeta>::default()
+///   .add_sink(EventSinkConfig::new(StdoutSink).min_level(IndigaugeLogLevel::Warn));
+/// ```
+pub trait EventSink: Send + 'static {
+  /// Delivers a batch of events to the sink.
+  fn send(&mut self, events: &[EventPayload]);
+}
+
+/// Appends events as JSON lines to a local file.
+#[cfg(not(target_family = "wasm"))]
+pub struct FileEventSink {
+  path: std::path::PathBuf,
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl FileEventSink {
+  pub fn new(path: impl Into<std::path::PathBuf>) -> Self {
+    Self { path: path.into() }
+  }
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl EventSink for FileEventSink {
+  fn send(&mut self, events: &[EventPayload]) {
+    use std::{fs::OpenOptions, io::Write};
+
+    if let Some(parent) = self.path.parent() {
+      let _ = std::fs::create_dir_all(parent);
+    }
+
+    let Ok(mut file) = OpenOptions::new().create(true).append(true).open(&self.path) else {
+      return;
+    };
+
+    let mut lines = Vec::new();
+    for event in events {
+      if serde_json::to_writer(&mut lines, event).is_ok() {
+        lines.push(b'\n');
+      }
+    }
+
+    let _ = file.write_all(&lines);
+  }
+}
+
+/// A sink together with its filter and batching settings.
+#[derive(Clone)]
+pub struct EventSinkConfig {
+  sink: Arc<Mutex<dyn EventSink>>,
+  min_level: Option<IndigaugeLogLevel>,
+  event_types: Vec<String>,
+  batch_size: usize,
+  flush_interval: Duration,
+}
+
+impl EventSinkConfig {
+  /// Creates a sink config that receives all events, flushed every 10 seconds or every 64 events.
+  pub fn new(sink: impl EventSink) -> Self {
+    Self {
+      sink: Arc::new(Mutex::new(sink)),
+      min_level: None,
+      event_types: Vec::new(),
+      batch_size: 64,
+      flush_interval: Duration::from_secs(10),
+    }
+  }
+
+  /// Only deliver events with this level or higher.
+  pub fn min_level(mut self, min_level: IndigaugeLogLevel) -> Self {
+    self.min_level = Some(min_level);
+    self
+  }
+
+  /// Only deliver events whose event type starts with one of the given prefixes, e.g. `"gameplay."`.
+  pub fn event_types<T>(mut self, event_types: Vec<T>) -> Self
+  where
+    T: Into<String>,
+  {
+    self.event_types = event_types.into_iter().map(Into::into).collect();
+    self
+  }
+
+  pub fn batch_size(mut self, batch_size: usize) -> Self {
+    self.batch_size = batch_size.max(1);
+    self
+  }
+
+  pub fn flush_interval(mut self, flush_interval: Duration) -> Self {
+    self.flush_interval = flush_interval;
+    self
+  }
+
+  pub(crate) fn matches(&self, event: &EventPayload) -> bool {
+    let level_matches = self
+      .min_level
+      .as_ref()
+      .is_none_or(|min_level| level_rank(event.level) >= min_level_rank(min_level));
+    let event_type_matches = self.event_types.is_empty()
+      || self
+        .event_types
+        .iter()
+        .any(|prefix| event.event_type.starts_with(prefix.as_str()));
+
+    level_matches && event_type_matches
+  }
+}
+
+fn level_rank(level: &str) -> u8 {
+  match level {
+    "trace" => 0,
+    "debug" => 1,
+    "info" => 2,
+    "warn" => 3,
+    "error" => 4,
+    _ => 5,
+  }
+}
+
+fn min_level_rank(level: &IndigaugeLogLevel) -> u8 {
+  match level {
+    #[cfg(feature = "tracing")]
+    IndigaugeLogLevel::Trace => 0,
+    IndigaugeLogLevel::Debug => 1,
+    IndigaugeLogLevel::Info => 2,
+    IndigaugeLogLevel::Warn => 3,
+    IndigaugeLogLevel::Error => 4,
+    IndigaugeLogLevel::Silent => u8::MAX,
+  }
+}
+
+/// Batches that were flushed but not delivered to the sink yet.
+#[derive(Default)]
+struct SinkQueue {
+  batches: VecDeque<Vec<EventPayload>>,
+  /// Whether a task is delivering the batches. At most one task per sink runs at a time.
+  in_flight: bool,
+}
+
+pub(crate) struct RegisteredSink {
+  config: EventSinkConfig,
+  buffer: Vec<EventPayload>,
+  queue: Arc<Mutex<SinkQueue>>,
+  last_flush: Instant,
+}
+
+/// Delivers the queued batches in order. Batches are taken from the queue while the sink is locked, so that a
+/// blocking flush can't deliver later batches ahead of them.
+fn deliver_queued(target: &Mutex<dyn EventSink>, queue: &Mutex<SinkQueue>) {
+  loop {
+    let Ok(mut target) = target.lock() else {
+      return;
+    };
+    let Ok(mut queue) = queue.lock() else {
+      return;
+    };
+
+    match queue.batches.pop_front() {
+      Some(batch) => {
+        drop(queue);
+        target.send(&batch);
+      },
+      None => {
+        queue.in_flight = false;
+        return;
+      },
+    }
+  }
+}
+
+/// Additional event sinks and their buffered events.
+#[derive(Resource, Default)]
+pub struct EventSinks {
+  pub(crate) sinks: Vec<RegisteredSink>,
+}
+
+impl EventSinks {
+  pub(crate) fn new(configs: Vec<EventSinkConfig>) -> Self {
+    Self {
+      sinks: configs
+        .into_iter()
+        .map(|config| RegisteredSink {
+          config,
+          buffer: Vec::new(),
+          queue: Arc::default(),
+          last_flush: Instant::now(),
+        })
+        .collect(),
+    }
+  }
+
+  /// Buffers the event for every sink whose filter matches it.
+  pub(crate) fn push(&mut self, event: &EventPayload) {
+    for sink in self.sinks.iter_mut().filter(|sink| sink.config.matches(event)) {
+      sink.buffer.push(event.clone());
+    }
+  }
+
+  /// Flushes every sink whose batch is full or whose flush interval has elapsed. The batch is queued behind the
+  /// batches of earlier flushes, and a delivery task is only spawned if none is running for the sink.
+  pub(crate) fn flush_due(&mut self) {
+    for sink in &mut self.sinks {
+      let is_due = sink.buffer.len() >= sink.config.batch_size
+        || (!sink.buffer.is_empty() && sink.last_flush.elapsed() >= sink.config.flush_interval);
+      if !is_due {
+        continue;
+      }
+
+      let Ok(mut queue) = sink.queue.lock() else {
+        continue;
+      };
+      let events = sink
+        .buffer
+        .drain(..sink.buffer.len().min(sink.config.batch_size))
+        .collect::<Vec<_>>();
+      queue.batches.push_back(events);
+      sink.last_flush = Instant::now();
+
+      if !queue.in_flight {
+        queue.in_flight = true;
+        let (target, queue) = (sink.config.sink.clone(), sink.queue.clone());
+        IoTaskPool::get()
+          .spawn(async move { deliver_queued(&*target, &queue) })
+          .detach();
+      }
+    }
+  }
+
+  /// Delivers all queued and buffered events on the calling thread, after the batches of earlier flushes.
+  pub(crate) fn flush_all_blocking(&mut self) {
+    for sink in &mut self.sinks {
+      let events = std::mem::take(&mut sink.buffer);
+      sink.last_flush = Instant::now();
+
+      let Ok(mut target) = sink.config.sink.lock() else {
+        continue;
+      };
+      let Ok(mut queue) = sink.queue.lock() else {
+        continue;
+      };
+
+      let queued = std::mem::take(&mut queue.batches);
+      drop(queue);
+      for batch in &queued {
+        target.send(batch);
+      }
+      for batch in events.chunks(sink.config.batch_size) {
+        target.send(batch);
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{
+    config::IndigaugeConfig,
+    event::{
+      resources::{BufferedEvents, EventQueueReceiver, QueuedEvent},
+      systems::handle_queued_events,
+    },
+  };
+
+  struct NullSink;
+
+  impl EventSink for NullSink {
+    fn send(&mut self, _events: &[EventPayload]) {}
+  }
+
+  /// Records the sequence numbers of the batches it receives, slowly.
+  struct SlowSink(Arc<Mutex<Vec<Vec<u64>>>>);
+
+  impl EventSink for SlowSink {
+    fn send(&mut self, events: &[EventPayload]) {
+      std::thread::sleep(Duration::from_millis(5));
+      self.0.lock().unwrap().push(events.iter().map(|event| event.seq).collect());
+    }
+  }
+
+  fn event(level: &'static str, event_type: &'static str) -> EventPayload {
+    EventPayload {
+      event_type: event_type.into(),
+      metadata: None,
+      level,
+      elapsed_ms: 0,
+      timestamp: 0,
+      seq: 0,
+      idempotency_key: None,
+      context: None,
+      game_time: None,
+    }
+  }
+
+  #[test]
+  fn sinks_filter_by_level_and_event_type_prefix() {
+    let all = EventSinkConfig::new(NullSink);
+    assert!(all.matches(&event("debug", "ui.click")));
+
+    let warnings = EventSinkConfig::new(NullSink).min_level(IndigaugeLogLevel::Warn);
+    assert!(!warnings.matches(&event("info", "ui.click")));
+    assert!(warnings.matches(&event("warn", "ui.click")));
+    assert!(warnings.matches(&event("fatal", "game.crash")));
+
+    let gameplay = EventSinkConfig::new(NullSink).event_types(vec!["gameplay.", "match."]);
+    assert!(gameplay.matches(&event("info", "match.end")));
+    assert!(!gameplay.matches(&event("info", "ui.click")));
+
+    let silent = EventSinkConfig::new(NullSink).min_level(IndigaugeLogLevel::Silent);
+    assert!(!silent.matches(&event("fatal", "game.crash")));
+  }
+
+  #[test]
+  fn sinks_receive_batches_one_at_a_time_and_in_order() {
+    bevy::tasks::IoTaskPool::get_or_init(bevy::tasks::TaskPool::new);
+    let received = Arc::new(Mutex::new(Vec::new()));
+    let mut sinks = EventSinks::new(vec![EventSinkConfig::new(SlowSink(received.clone())).batch_size(2)]);
+
+    for seq in 0..7 {
+      sinks.push(&EventPayload {
+        seq,
+        ..event("info", "game.tick")
+      });
+      sinks.flush_due();
+    }
+    sinks.flush_all_blocking();
+
+    assert_eq!(*received.lock().unwrap(), vec![vec![0, 1], vec![2, 3], vec![4, 5], vec![6]]);
+  }
+
+  #[test]
+  fn sinks_receive_events_before_the_session_is_established() {
+    let (tx, rx) = crossbeam_channel::unbounded();
+    let mut app = App::new();
+    app
+      .insert_resource(EventQueueReceiver::new(rx))
+      .insert_resource(EventSinks::new(vec![EventSinkConfig::new(NullSink)]))
+      .insert_resource(IndigaugeConfig::new("game", "key", "1.0.0"))
+      .insert_resource(IndigaugeLogLevel::Error)
+      .init_resource::<BufferedEvents>()
+      .add_systems(Update, handle_queued_events);
+
+    tx.send(QueuedEvent::new(event("info", "game.start"), 0)).unwrap();
+    app.update();
+
+    assert_eq!(app.world().resource::<EventSinks>().sinks[0].buffer.len(), 1);
+  }
+}
@@ -1,39 +1,84 @@
-use bevy::prelude::*;
+use bevy::{core::FrameCount, ecs::system::SystemParam, prelude::*};
 
 use crate::{
-  config::IndigaugeLogLevel,
-  event::resources::{BufferedEvents, EventQueueReceiver},
+  api_types::EventGameTime,
+  config::{IndigaugeConfig, IndigaugeLogLevel},
+  event::resources::{BufferedEvents, EventQueueReceiver, FixedTicks},
+  event::sinks::EventSinks,
+  remote_config::IndigaugeRemoteConfig,
   session::resources::SessionApiKey,
   utils::BevyIndigauge,
 };
 
+#[derive(SystemParam)]
+pub struct GameClock<'w> {
+  frame_count: Option<Res<'w, FrameCount>>,
+  virtual_time: Option<Res<'w, Time<Virtual>>>,
+  fixed_ticks: Option<Res<'w, FixedTicks>>,
+}
+
+impl GameClock<'_> {
+  pub fn game_time(&self) -> EventGameTime {
+    EventGameTime {
+      frame: self.frame_count.as_ref().map(|frame_count| frame_count.0).unwrap_or_default(),
+      virtual_elapsed_ms: self
+        .virtual_time
+        .as_ref()
+        .map(|time| time.elapsed().as_millis())
+        .unwrap_or_default(),
+      fixed_tick: self.fixed_ticks.as_ref().map(|ticks| ticks.0).unwrap_or_default(),
+    }
+  }
+}
+
+pub(crate) fn count_fixed_ticks(mut fixed_ticks: ResMut<FixedTicks>) {
+  fixed_ticks.0 += 1;
+}
+
 pub fn maybe_flush_events(mut ig: BevyIndigauge, session_key: Res<SessionApiKey>) {
-  if ig.buffered_events.events.len() >= ig.config.batch_size {
+  if !ig.transport.is_rate_limited() && ig.buffered_events.is_batch_full(&ig.config) {
     ig.flush_events(&session_key);
   }
 }
 
+pub fn maybe_flush_priority_events(mut ig: BevyIndigauge, session_key: Res<SessionApiKey>) {
+  if !ig.transport.is_rate_limited() && ig.buffered_events.is_priority_flush_due(&ig.config) {
+    ig.flush_priority_events(&session_key);
+  }
+}
+
 pub fn flush_events(mut ig: BevyIndigauge, session_key: Res<SessionApiKey>) {
-  if ig.flush_events(&session_key) == 0 {
-    ig.send_heartbeat(&session_key);
+  if !ig.transport.is_rate_limited() {
+    ig.flush_events(&session_key);
   }
 }
 
+pub fn flush_event_sinks(mut sinks: ResMut<EventSinks>) {
+  sinks.flush_due();
+}
+
 pub fn handle_queued_events(
   receiver: Res<EventQueueReceiver>,
   mut buffered_events: ResMut<BufferedEvents>,
+  mut sinks: ResMut<EventSinks>,
   log_level: Res<IndigaugeLogLevel>,
+  config: Res<IndigaugeConfig>,
+  remote_config: Option<Res<IndigaugeRemoteConfig>>,
+  game_clock: GameClock,
 ) {
-  for event in receiver.try_iter() {
-    match event.validate() {
-      Ok(_) => {
-        buffered_events.events.push(event);
-      },
-      Err(error) => {
-        if *log_level <= IndigaugeLogLevel::Error {
-          error!(message = "Invalid event", ?error);
-        }
-      },
+  let game_time = config.stamp_game_time.then(|| game_clock.game_time());
+
+  for mut event in receiver.try_iter() {
+    if let Some(game_time) = &game_time {
+      event.stamp_game_time(game_time.clone());
+    }
+
+    if event.prepare(&config, &log_level) {
+      sinks.push(event.payload());
+      // The remote config only decides what is sent to Indigauge. Sinks receive every event.
+      if remote_config.as_ref().is_none_or(|remote_config| remote_config.accepts(event.payload())) {
+        buffered_events.push(event, &config);
+      }
     }
   }
 }
@@ -1,12 +1,26 @@
-use std::time::Instant;
+use std::{borrow::Cow, time::Instant};
+
+use crossbeam_channel::{Receiver, Sender, bounded};
+use once_cell::sync::OnceCell;
 
 use crate::{
   api_types::{EventPayload, EventPayloadCtx},
-  event::resources::QueuedEvent,
-  plugin::GLOBAL_TX,
-  session::SESSION_START_INSTANT,
+  event::{metadata::EventMetadata, resources::QueuedEvent},
+  session::{SESSION_START_INSTANT, next_event_seq},
+  utils::unix_timestamp_ms,
 };
 
+pub(crate) static GLOBAL_TX: OnceCell<Sender<QueuedEvent>> = OnceCell::new();
+
+/// Creates the process-wide event queue that [`enqueue`] sends to. Returns the receiving end, or `None` if
+/// the queue was already created by another plugin or client.
+pub(crate) fn init_event_queue(max_queue: usize) -> Option<Receiver<QueuedEvent>> {
+  let (tx, rx) = bounded::<QueuedEvent>(max_queue);
+  GLOBAL_TX.set(tx).ok().map(|_| rx)
+}
+
+/// Enqueues an event for the current session. Returns whether the event was accepted, i.e. whether a session was
+/// started and the queue isn't full.
 #[inline]
 pub fn enqueue(
   level: &'static str,
@@ -15,6 +29,20 @@ pub fn enqueue(
   file: &'static str,
   line: u32,
   module: &'static str,
+) -> bool {
+  enqueue_event(level, event_type.to_string(), metadata.map(EventMetadata::Json), file, line, module)
+}
+
+/// Like [`enqueue`], but a `&'static str` event type is kept without copying it, and the metadata can be captured
+/// inline, as the `ig_*!` macros do.
+#[inline]
+pub fn enqueue_event(
+  level: &'static str,
+  event_type: impl Into<Cow<'static, str>>,
+  metadata: Option<EventMetadata>,
+  file: &'static str,
+  line: u32,
+  module: &'static str,
 ) -> bool {
   let tx = match GLOBAL_TX.get() {
     Some(tx) => tx.clone(),
@@ -26,21 +54,25 @@ pub fn enqueue(
     let module = if module.is_empty() { None } else { Some(module) };
 
     let context = matches!(level, "warn" | "error").then(|| EventPayloadCtx {
-      file: file.to_string(),
+      file: Cow::Borrowed(file),
       line,
       module,
     });
 
+    let (generation, seq) = next_event_seq();
     let payload = EventPayload {
       level,
-      event_type: event_type.to_string(),
+      event_type: event_type.into(),
       elapsed_ms,
+      timestamp: unix_timestamp_ms(),
+      seq,
       metadata,
       idempotency_key: None,
       context,
+      game_time: None,
     };
 
-    tx.try_send(QueuedEvent::new(payload)).is_ok()
+    tx.try_send(QueuedEvent::new(payload, generation)).is_ok()
   } else {
     false
   }
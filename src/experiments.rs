@@ -0,0 +1,285 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use bevy::prelude::*;
+use serde::Deserialize;
+
+use crate::{
+  event::{metadata::EventMetadata, utils::enqueue_event},
+  identity::IndigaugePlayer,
+  remote_config::{IndigaugeRemoteConfig, IndigaugeRemoteConfigChanged},
+};
+
+/// A variant of an [`Experiment`] and its relative weight.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct ExperimentVariant {
+  pub name: String,
+  #[serde(default = "default_weight")]
+  pub weight: u32,
+}
+
+fn default_weight() -> u32 {
+  1
+}
+
+/// An A/B experiment. Players are assigned to one of its variants, weighted by the variant weights.
+///
+/// Experiments can be defined in code with [`IndigaugePlugin::experiment`](crate::prelude::IndigaugePlugin::experiment)
+/// or in the `experiments` list of the remote config, which replaces experiments of the same name defined in code:
+///
+/// ```json
+/// { "experiments": [{ "name": "new_tutorial", "variants": [{ "name": "a" }, { "name": "b", "weight": 3 }] }] }
+/// ```
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct Experiment {
+  pub name: String,
+  pub variants: Vec<ExperimentVariant>,
+}
+
+impl Experiment {
+  pub fn new(name: impl Into<String>) -> Self {
+    Self {
+      name: name.into(),
+      variants: Vec::new(),
+    }
+  }
+
+  pub fn variant(mut self, name: impl Into<String>, weight: u32) -> Self {
+    self.variants.push(ExperimentVariant {
+      name: name.into(),
+      weight,
+    });
+    self
+  }
+
+  /// Picks the variant of the player by hashing the player id with the experiment name.
+  fn assign(&self, player_id: &str) -> Option<&str> {
+    let total_weight = self.variants.iter().map(|variant| u64::from(variant.weight)).sum::<u64>();
+    if total_weight == 0 {
+      return None;
+    }
+
+    let mut bucket = fnv1a(&[self.name.as_bytes(), b":", player_id.as_bytes()]) % total_weight;
+    self
+      .variants
+      .iter()
+      .find(|variant| {
+        let in_variant = bucket < u64::from(variant.weight);
+        bucket = bucket.saturating_sub(u64::from(variant.weight));
+        in_variant
+      })
+      .map(|variant| variant.name.as_str())
+  }
+}
+
+/// 64-bit FNV-1a. Unlike the std hashers, it is stable across Rust versions and platforms, so that players keep
+/// their variant.
+pub(crate) fn fnv1a(parts: &[&[u8]]) -> u64 {
+  parts.iter().flat_map(|part| part.iter()).fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+    (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+  })
+}
+
+/// The variants the player is assigned to. Assignments are deterministic for the player id, so the player stays in
+/// the same variant across sessions. The player is reassigned when identified or reset, see [`IndigaugePlayer`].
+///
+/// The first time a variant is observed in a session, through [`Experiments::variant`] or [`in_variant`], an
+/// `experiment.exposure` event is sent. This includes a new variant after the player was reassigned.
+///
+/// # Example
+/// ```
+/// use bevy::prelude::*;
+/// use bevy_mod_indigauge::prelude::*;
+///
+/// fn spawn_tutorial(experiments: Res<Experiments>) {
+///   if experiments.variant("new_tutorial") == Some("b") {
+///     // ...
+///   }
+/// }
+/// ```
+#[derive(Resource, Debug)]
+pub struct Experiments {
+  player_id: String,
+  /// Experiments defined in code.
+  defined: Vec<Experiment>,
+  assignments: HashMap<String, String>,
+  /// The variant of each experiment that was last observed in this session. The new variant is exposed when the
+  /// player is reassigned.
+  exposed: Mutex<HashMap<String, String>>,
+}
+
+impl Experiments {
+  pub(crate) fn new(player_id: String, defined: Vec<Experiment>, remote_config: &IndigaugeRemoteConfig) -> Self {
+    let mut experiments = Self {
+      player_id,
+      defined,
+      assignments: HashMap::new(),
+      exposed: Mutex::default(),
+    };
+    experiments.assign(remote_config);
+    experiments
+  }
+
+  /// Assigns the player to the experiments defined in code and in the remote config.
+  fn assign(&mut self, remote_config: &IndigaugeRemoteConfig) {
+    let remote = remote_config.get::<Vec<Experiment>>("experiments").unwrap_or_default();
+    let defined = self
+      .defined
+      .iter()
+      .filter(|experiment| !remote.iter().any(|remote| remote.name == experiment.name));
+
+    self.assignments = defined
+      .chain(remote.iter())
+      .filter_map(|experiment| {
+        let variant = experiment.assign(&self.player_id)?;
+        Some((experiment.name.clone(), variant.to_string()))
+      })
+      .collect();
+  }
+
+  /// The variant the player is assigned to, or `None` if the experiment is unknown. Sends an exposure event the
+  /// first time the variant of the experiment is observed in this session.
+  pub fn variant(&self, experiment: &str) -> Option<&str> {
+    let variant = self.assignments.get(experiment)?;
+    self.expose(experiment, variant);
+    Some(variant)
+  }
+
+  /// Whether the player is assigned to the given variant. Counts as an observation, see [`Experiments::variant`].
+  pub fn is_in(&self, experiment: &str, variant: &str) -> bool {
+    self.variant(experiment) == Some(variant)
+  }
+
+  /// Every assignment, without sending exposure events.
+  pub fn assignments(&self) -> impl Iterator<Item = (&str, &str)> {
+    self
+      .assignments
+      .iter()
+      .map(|(experiment, variant)| (experiment.as_str(), variant.as_str()))
+  }
+
+  fn expose(&self, experiment: &str, variant: &str) {
+    let Ok(mut exposed) = self.exposed.lock() else {
+      return;
+    };
+    if exposed.get(experiment).is_some_and(|exposed| exposed == variant) {
+      return;
+    }
+
+    let metadata = crate::ig_metadata!({ "experiment": experiment, "variant": variant });
+    // Not marked as exposed until the event is accepted, e.g. once the session was started.
+    if enqueue_event(
+      "info",
+      "experiment.exposure",
+      Some(EventMetadata::Inline(metadata)),
+      file!(),
+      line!(),
+      module_path!(),
+    ) {
+      match exposed.get_mut(experiment) {
+        Some(exposed) => variant.clone_into(exposed),
+        None => {
+          exposed.insert(experiment.to_string(), variant.to_string());
+        },
+      }
+    }
+  }
+}
+
+/// Reassigns the player when their id changes, i.e. when the player is identified or the identity is reset, so that
+/// the assignments match the player id sessions are sent with.
+pub(crate) fn reassign_on_identity_change(
+  player: Res<IndigaugePlayer>,
+  remote_config: Res<IndigaugeRemoteConfig>,
+  mut experiments: ResMut<Experiments>,
+) {
+  if experiments.player_id != player.id() {
+    experiments.player_id = player.id().to_string();
+    experiments.assign(&remote_config);
+  }
+}
+
+/// Reassigns the player when a different remote config is received.
+pub(crate) fn observe_remote_config_changed(
+  trigger: Trigger<IndigaugeRemoteConfigChanged>,
+  mut experiments: ResMut<Experiments>,
+) {
+  experiments.assign(&trigger.event().current);
+}
+
+/// Run condition that is true while the player is assigned to the given variant of the experiment. Counts as an
+/// observation, see [`Experiments::variant`].
+///
+/// # Example
+/// ```rust,ignore
+/// app.add_systems(OnEnter(GameState::Tutorial), spawn_new_tutorial.run_if(in_variant("new_tutorial", "b")));
+/// ```
+pub fn in_variant(
+  experiment: &'static str,
+  variant: &'static str,
+) -> impl FnMut(Option<Res<Experiments>>) -> bool + Clone {
+  move |experiments: Option<Res<Experiments>>| {
+    experiments.is_some_and(|experiments| experiments.is_in(experiment, variant))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::remote_config::RemoteConfigSource;
+  use bevy::ecs::system::RunSystemOnce;
+  use serde_json::json;
+
+  #[test]
+  fn players_are_assigned_deterministically_by_weight() {
+    let experiment = Experiment::new("new_tutorial").variant("a", 1).variant("b", 3);
+    let assignments = (0..1000)
+      .map(|player| experiment.assign(&format!("player-{player}")).unwrap())
+      .collect::<Vec<_>>();
+
+    let in_b = assignments.iter().filter(|variant| **variant == "b").count();
+    assert!((650..850).contains(&in_b));
+    assert_eq!(experiment.assign("player-1"), Some(assignments[1]));
+    assert_eq!(Experiment::new("empty").assign("player-1"), None);
+  }
+
+  #[test]
+  fn remote_experiments_replace_experiments_defined_in_code() {
+    let remote_config = IndigaugeRemoteConfig::new(
+      json!({ "experiments": [{ "name": "new_tutorial", "variants": [{ "name": "c" }] }] }),
+      RemoteConfigSource::Server,
+    );
+    let experiments = Experiments::new(
+      "player".to_string(),
+      vec![Experiment::new("new_tutorial").variant("a", 1), Experiment::new("shop").variant("old", 1)],
+      &remote_config,
+    );
+
+    assert_eq!(experiments.variant("new_tutorial"), Some("c"));
+    assert_eq!(experiments.variant("shop"), Some("old"));
+    assert_eq!(experiments.variant("unknown"), None);
+  }
+
+  #[test]
+  fn players_are_reassigned_when_their_identity_changes() {
+    let experiment = Experiment::new("new_tutorial").variant("a", 1).variant("b", 1);
+    let remote_config = IndigaugeRemoteConfig::new(json!({}), RemoteConfigSource::Cached);
+    let player = IndigaugePlayer::new("anonymous".to_string(), None, Vec::new());
+    let other_variant = if experiment.assign(player.id()) == Some("a") { "b" } else { "a" };
+    let account = (0..)
+      .map(|account| format!("account-{account}"))
+      .find(|account| experiment.assign(account) == Some(other_variant))
+      .unwrap();
+
+    let mut experiments = Experiments::new(account, vec![experiment], &remote_config);
+    assert_eq!(experiments.variant("new_tutorial"), Some(other_variant));
+
+    let mut world = World::new();
+    world.insert_resource(player);
+    world.insert_resource(remote_config);
+    world.insert_resource(experiments);
+    world.run_system_once(reassign_on_identity_change).unwrap();
+
+    experiments = world.remove_resource::<Experiments>().unwrap();
+    assert_ne!(experiments.variant("new_tutorial"), Some(other_variant));
+  }
+}
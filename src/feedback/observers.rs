@@ -6,14 +6,13 @@ use bevy::{
   state::state::FreelyMutableState,
 };
 use bevy_mod_reqwest::ReqwestResponseEvent;
-use image::{ColorType, ImageEncoder, codecs::png::PngEncoder};
 
 use crate::{
   api_types::{FeedbackPayload, IdResponse},
   feedback::components::{CategoryButtonText, CategoryItem, FeedbackPanel, MessageInput, ScreenshotToggleText},
   feedback::resources::{FeedbackFormState, TakeScreenshot},
   prelude::*,
-  session::SESSION_START_INSTANT,
+  session::{SESSION_START_INSTANT, local_session_id},
   session::resources::SessionApiKey,
   utils::BevyIndigauge,
   utils::select,
@@ -122,6 +121,7 @@ pub fn observe_submit_click(
       category: form.category.label().to_lowercase(),
       elapsed_ms,
       question: form.question.as_ref(),
+      local_session_id: local_session_id(),
     };
 
     ig.send_feedback(&session_key, &payload, maybe_take_screenshot);
@@ -142,28 +142,7 @@ fn maybe_take_screenshot(
     commands.spawn(Screenshot::primary_window()).observe(
       move |trigger: Trigger<ScreenshotCaptured>, mut ig: BevyIndigauge, api_key: Res<SessionApiKey>| {
         let img = trigger.event().deref().clone();
-
-        match img.try_into_dynamic() {
-          Ok(dyn_img) => {
-            let data = dyn_img.to_rgb8().to_vec();
-            let mut png = Vec::new();
-            let enc = PngEncoder::new(&mut png);
-
-            if enc
-              .write_image(&data, dyn_img.width(), dyn_img.height(), ColorType::Rgb8)
-              .is_ok()
-            {
-              ig.send_feedback_screenshot(&api_key, &feedback_id, png);
-            } else if *ig.log_level <= IndigaugeLogLevel::Error {
-              error!(message = "Failed to encode screenshot as PNG");
-            }
-          },
-          Err(error) => {
-            if *ig.log_level <= IndigaugeLogLevel::Error {
-              error!(message = "Failed to convert screenshot into dynamic image", ?error);
-            }
-          },
-        }
+        ig.send_feedback_screenshot(&api_key, &feedback_id, img);
       },
     );
   }
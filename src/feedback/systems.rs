@@ -11,6 +11,7 @@ use crate::{
     observe_screenshot_toggle_click, observe_submit_click,
   },
   feedback::{components::*, helpers::*, resources::*, types::FeedbackCategory},
+  remote_config::IndigaugeRemoteConfig,
   utils::select,
 };
 
@@ -26,8 +27,10 @@ pub fn toggle_panel_visibility_with_key(
   mut commands: Commands,
   keys: Res<ButtonInput<KeyCode>>,
   toggle_button: Res<FeedbackKeyCodeToggle>,
+  remote_config: Option<Res<IndigaugeRemoteConfig>>,
 ) {
-  if keys.just_pressed(toggle_button.0) {
+  let feedback_enabled = remote_config.is_none_or(|remote_config| remote_config.sdk().feedback_enabled);
+  if feedback_enabled && keys.just_pressed(toggle_button.0) {
     commands.insert_resource(FeedbackPanelProps::default());
   }
 }
@@ -0,0 +1,489 @@
+use std::time::Instant;
+
+use bevy::prelude::*;
+
+use crate::{
+  api_types::PlayerIdentityPayload,
+  config::{IndigaugeConfig, IndigaugeLogLevel},
+  session::{resources::SessionApiKey, utils::generate_session_id},
+  utils::{BevyIndigauge, unix_timestamp_ms},
+};
+
+/// The identity of the player, sent as the player id when a session is started.
+///
+/// Players start out with an anonymous id that is generated once per device. When the game identifies the player
+/// with its own id, e.g. a hashed platform account id, that id is used instead and the anonymous id is linked to it.
+/// Both ids are persisted, in `localStorage` on wasm, so the player stays identified across launches until the
+/// identity is reset. Identity changes are reported to the server as soon as a session is established. They are
+/// persisted too until the server confirmed them, and retried if reporting them failed.
+///
+/// # Example
+/// ```rust,ignore
+/// fn on_login(mut commands: Commands, account: Res<Account>) {
+///   commands.trigger(IdentifyPlayerEvent::new(account.hashed_id()));
+/// }
+///
+/// fn on_logout(mut commands: Commands) {
+///   commands.trigger(ResetPlayerEvent);
+/// }
+/// ```
+#[derive(Resource, Clone, Debug)]
+pub struct IndigaugePlayer {
+  anonymous_id: String,
+  custom_id: Option<String>,
+  /// Identity changes that weren't reported to the server yet.
+  pending: Vec<PlayerIdentityPayload>,
+  /// Identity changes that were sent, but not confirmed by the server yet.
+  in_flight: Vec<PlayerIdentityPayload>,
+  /// Set after reporting a change failed. Pending changes are held back until then.
+  retry_at: Option<Instant>,
+}
+
+impl IndigaugePlayer {
+  pub(crate) fn new(anonymous_id: String, custom_id: Option<String>, pending: Vec<PlayerIdentityPayload>) -> Self {
+    Self {
+      anonymous_id,
+      custom_id,
+      pending,
+      in_flight: Vec::new(),
+      retry_at: None,
+    }
+  }
+
+  /// The identity persisted for the game, generating the anonymous id on first use. Changes that weren't confirmed
+  /// by the server before the game exited are reported again.
+  pub(crate) fn load(game_name: &str) -> Self {
+    Self::new(store::anonymous_id(game_name), store::custom_id(game_name), store::pending(game_name))
+  }
+
+  /// The game-provided id if the player was identified, otherwise the anonymous id.
+  pub fn id(&self) -> &str {
+    self.custom_id.as_deref().unwrap_or(&self.anonymous_id)
+  }
+
+  pub fn anonymous_id(&self) -> &str {
+    &self.anonymous_id
+  }
+
+  /// The game-provided id, or `None` if the player wasn't identified.
+  pub fn custom_id(&self) -> Option<&str> {
+    self.custom_id.as_deref()
+  }
+
+  pub fn is_identified(&self) -> bool {
+    self.custom_id.is_some()
+  }
+
+  /// Switches to the game-provided id. The anonymous id is only linked when an anonymous player logs in, not when
+  /// switching between accounts. Returns whether the identity changed.
+  fn identify(&mut self, player_id: &str, link_anonymous_id: bool) -> bool {
+    if self.custom_id.as_deref() == Some(player_id) {
+      return false;
+    }
+
+    let anonymous_id = (link_anonymous_id && !self.is_identified()).then(|| self.anonymous_id.clone());
+    self.pending.push(PlayerIdentityPayload {
+      player_id: player_id.to_string(),
+      previous_player_id: self.id().to_string(),
+      anonymous_id,
+      changed_at: unix_timestamp_ms(),
+    });
+    self.custom_id = Some(player_id.to_string());
+    true
+  }
+
+  /// Forgets the game-provided id and replaces the anonymous id, so that whoever plays next isn't linked to the
+  /// previous account. Returns whether the identity changed.
+  fn reset(&mut self, anonymous_id: String) -> bool {
+    if !self.is_identified() {
+      return false;
+    }
+
+    self.pending.push(PlayerIdentityPayload {
+      player_id: anonymous_id.clone(),
+      previous_player_id: self.id().to_string(),
+      anonymous_id: None,
+      changed_at: unix_timestamp_ms(),
+    });
+    self.custom_id = None;
+    self.anonymous_id = anonymous_id;
+    true
+  }
+
+  /// Moves the pending changes in flight, unless a failed change is waiting to be retried.
+  fn take_pending(&mut self, now: Instant) -> Vec<PlayerIdentityPayload> {
+    if self.retry_at.is_some_and(|retry_at| now < retry_at) {
+      return Vec::new();
+    }
+
+    self.retry_at = None;
+    let changes = std::mem::take(&mut self.pending);
+    self.in_flight.extend(changes.iter().cloned());
+    changes
+  }
+
+  /// Forgets a change once the server confirmed it, or rejected it for good.
+  fn settle(&mut self, change: &PlayerIdentityPayload) {
+    self.in_flight.retain(|in_flight| in_flight != change);
+  }
+
+  /// Queues a change that failed to be reported again, in the order the changes were made.
+  fn requeue(&mut self, change: PlayerIdentityPayload, retry_at: Instant) {
+    self.settle(&change);
+    let index = self.pending.partition_point(|pending| pending.changed_at <= change.changed_at);
+    self.pending.insert(index, change);
+    self.retry_at = Some(retry_at);
+  }
+
+  /// Changes that weren't confirmed by the server, oldest first.
+  fn unconfirmed(&self) -> Vec<PlayerIdentityPayload> {
+    let mut changes = self.in_flight.iter().chain(&self.pending).cloned().collect::<Vec<_>>();
+    changes.sort_by_key(|change| change.changed_at);
+    changes
+  }
+
+  fn persist_pending(&self, game_name: &str, log_level: &IndigaugeLogLevel) {
+    if let Err(error) = store::write_pending(game_name, &self.unconfirmed())
+      && *log_level <= IndigaugeLogLevel::Warn
+    {
+      warn!(message = "Failed to persist player identity changes", ?error);
+    }
+  }
+}
+
+/// Settles an identity change that was reported to the server. Changes that failed for a reason worth retrying are
+/// reported again after the flush interval, e.g. once the session was continued after a `401 Unauthorized`.
+pub(crate) struct SettleIdentityChange {
+  pub change: PlayerIdentityPayload,
+  pub retry: bool,
+}
+
+impl Command for SettleIdentityChange {
+  fn apply(self, world: &mut World) {
+    let config = world.resource::<IndigaugeConfig>();
+    let (game_name, retry_at) = (config.game_name.clone(), Instant::now() + config.flush_interval);
+    let log_level = world.resource::<IndigaugeLogLevel>().clone();
+
+    let mut player = world.resource_mut::<IndigaugePlayer>();
+    if self.retry {
+      player.requeue(self.change, retry_at);
+    } else {
+      player.settle(&self.change);
+    }
+    player.persist_pending(&game_name, &log_level);
+  }
+}
+
+/// Identifies the player with a game-provided id, e.g. when the player logs in. Sessions started afterwards are
+/// sent with this id, see [`IndigaugePlayer`].
+///
+/// ```rust,ignore
+/// commands.trigger(IdentifyPlayerEvent::new(account.hashed_id()));
+/// ```
+#[derive(Event, Clone, Debug)]
+pub struct IdentifyPlayerEvent {
+  pub player_id: String,
+  /// Whether the anonymous id is linked to the player id, attributing anonymous data to the player. Defaults to
+  /// `true`.
+  pub link_anonymous_id: bool,
+}
+
+impl IdentifyPlayerEvent {
+  pub fn new(player_id: impl Into<String>) -> Self {
+    Self {
+      player_id: player_id.into(),
+      link_anonymous_id: true,
+    }
+  }
+
+  /// Identifies the player without linking the anonymous id.
+  pub fn without_link(mut self) -> Self {
+    self.link_anonymous_id = false;
+    self
+  }
+}
+
+/// Resets the identity of the player, e.g. when the player logs out. A new anonymous id is generated, see
+/// [`IndigaugePlayer`].
+#[derive(Event, Clone, Copy, Debug, Default)]
+pub struct ResetPlayerEvent;
+
+pub(crate) fn observe_identify_player_event(
+  trigger: Trigger<IdentifyPlayerEvent>,
+  mut player: ResMut<IndigaugePlayer>,
+  config: Res<IndigaugeConfig>,
+  log_level: Res<IndigaugeLogLevel>,
+) {
+  let event = trigger.event();
+  if event.player_id.is_empty() {
+    if *log_level <= IndigaugeLogLevel::Warn {
+      warn!("Cannot identify the player with an empty id");
+    }
+    return;
+  }
+
+  if !player.identify(&event.player_id, event.link_anonymous_id) {
+    return;
+  }
+
+  if let Err(error) = store::write_custom_id(&config.game_name, player.custom_id())
+    && *log_level <= IndigaugeLogLevel::Warn
+  {
+    warn!(message = "Failed to persist player id", ?error);
+  }
+  player.persist_pending(&config.game_name, &log_level);
+}
+
+pub(crate) fn observe_reset_player_event(
+  _trigger: Trigger<ResetPlayerEvent>,
+  mut player: ResMut<IndigaugePlayer>,
+  config: Res<IndigaugeConfig>,
+  log_level: Res<IndigaugeLogLevel>,
+) {
+  if !player.reset(generate_session_id()) {
+    return;
+  }
+
+  let persisted = store::write_custom_id(&config.game_name, None)
+    .and_then(|()| store::write_anonymous_id(&config.game_name, player.anonymous_id()));
+  if let Err(error) = persisted
+    && *log_level <= IndigaugeLogLevel::Warn
+  {
+    warn!(message = "Failed to persist player id", ?error);
+  }
+  player.persist_pending(&config.game_name, &log_level);
+}
+
+/// Reports identity changes to the server once a session is established.
+pub(crate) fn report_identity_changes(
+  mut ig: BevyIndigauge,
+  mut player: ResMut<IndigaugePlayer>,
+  session_key: Res<SessionApiKey>,
+) {
+  if player.pending.is_empty() {
+    return;
+  }
+
+  let mut settled = false;
+  for change in player.take_pending(Instant::now()) {
+    // Changes that aren't sent, e.g. in dev mode, are settled right away.
+    if !ig.send_identity_change(&session_key, &change) {
+      player.settle(&change);
+      settled = true;
+    }
+  }
+
+  if settled {
+    player.persist_pending(&ig.config.game_name, &ig.log_level);
+  }
+}
+
+#[cfg(not(target_family = "wasm"))]
+mod store {
+  use std::{fs, path::PathBuf};
+
+  use crate::{api_types::PlayerIdentityPayload, utils::get_or_init_player_id};
+
+  fn custom_id_path(game_name: &str) -> Option<PathBuf> {
+    dirs::preference_dir().map(|dir| dir.join(game_name).join("custom_player_id.txt"))
+  }
+
+  fn pending_path(game_name: &str) -> Option<PathBuf> {
+    dirs::preference_dir().map(|dir| dir.join(game_name).join("pending_player_identity.json"))
+  }
+
+  fn remove(path: Option<PathBuf>) -> std::io::Result<()> {
+    match path.map(fs::remove_file) {
+      Some(Err(error)) if error.kind() != std::io::ErrorKind::NotFound => Err(error),
+      _ => Ok(()),
+    }
+  }
+
+  fn write(path: Option<PathBuf>, contents: &str) -> std::io::Result<()> {
+    let path = path.ok_or(std::io::ErrorKind::NotFound)?;
+    if let Some(dir) = path.parent() {
+      fs::create_dir_all(dir)?;
+    }
+    fs::write(path, contents)
+  }
+
+  pub(super) fn anonymous_id(game_name: &str) -> String {
+    get_or_init_player_id(game_name)
+  }
+
+  pub(super) fn custom_id(game_name: &str) -> Option<String> {
+    let custom_id = fs::read_to_string(custom_id_path(game_name)?).ok()?;
+    Some(custom_id.trim().to_string()).filter(|custom_id| !custom_id.is_empty())
+  }
+
+  pub(super) fn write_anonymous_id(game_name: &str, anonymous_id: &str) -> std::io::Result<()> {
+    write(
+      dirs::preference_dir().map(|dir| dir.join(game_name).join("player_id.txt")),
+      anonymous_id,
+    )
+  }
+
+  pub(super) fn write_custom_id(game_name: &str, custom_id: Option<&str>) -> std::io::Result<()> {
+    match custom_id {
+      Some(custom_id) => write(custom_id_path(game_name), custom_id),
+      None => remove(custom_id_path(game_name)),
+    }
+  }
+
+  pub(super) fn pending(game_name: &str) -> Vec<PlayerIdentityPayload> {
+    pending_path(game_name)
+      .and_then(|path| fs::read(path).ok())
+      .and_then(|json| serde_json::from_slice(&json).ok())
+      .unwrap_or_default()
+  }
+
+  pub(super) fn write_pending(game_name: &str, pending: &[PlayerIdentityPayload]) -> std::io::Result<()> {
+    if pending.is_empty() {
+      return remove(pending_path(game_name));
+    }
+    write(pending_path(game_name), &serde_json::to_string(pending)?)
+  }
+}
+
+/// Identities are kept in `localStorage`. If the storage is unavailable, e.g. in private browsing or when it is
+/// disabled, the anonymous id only lasts for the session.
+#[cfg(target_family = "wasm")]
+mod store {
+  use web_sys::Storage;
+
+  use crate::{
+    api_types::PlayerIdentityPayload,
+    session::{init_local_session_id, utils::generate_session_id},
+  };
+
+  fn local_storage() -> Option<Storage> {
+    web_sys::window()?.local_storage().ok().flatten()
+  }
+
+  fn storage_key(game_name: &str, name: &str) -> String {
+    format!("indigauge.{game_name}.{name}")
+  }
+
+  fn write(key: &str, value: Option<&str>) -> std::io::Result<()> {
+    let storage = local_storage().ok_or(std::io::ErrorKind::NotFound)?;
+    let result = match value {
+      Some(value) => storage.set_item(key, value),
+      None => storage.remove_item(key),
+    };
+    result.map_err(|error| std::io::Error::other(format!("{error:?}")))
+  }
+
+  fn read(key: &str) -> Option<String> {
+    let value = local_storage()?.get_item(key).ok().flatten()?;
+    Some(value).filter(|value| !value.is_empty())
+  }
+
+  pub(super) fn anonymous_id(game_name: &str) -> String {
+    let key = storage_key(game_name, "player_id");
+    if let Some(anonymous_id) = read(&key) {
+      return anonymous_id;
+    }
+
+    let anonymous_id = generate_session_id();
+    match write(&key, Some(&anonymous_id)) {
+      Ok(()) => anonymous_id,
+      Err(_) => init_local_session_id().to_string(),
+    }
+  }
+
+  pub(super) fn custom_id(game_name: &str) -> Option<String> {
+    read(&storage_key(game_name, "custom_player_id"))
+  }
+
+  pub(super) fn write_anonymous_id(game_name: &str, anonymous_id: &str) -> std::io::Result<()> {
+    write(&storage_key(game_name, "player_id"), Some(anonymous_id))
+  }
+
+  pub(super) fn write_custom_id(game_name: &str, custom_id: Option<&str>) -> std::io::Result<()> {
+    write(&storage_key(game_name, "custom_player_id"), custom_id)
+  }
+
+  pub(super) fn pending(game_name: &str) -> Vec<PlayerIdentityPayload> {
+    read(&storage_key(game_name, "pending_player_identity"))
+      .and_then(|json| serde_json::from_str(&json).ok())
+      .unwrap_or_default()
+  }
+
+  pub(super) fn write_pending(game_name: &str, pending: &[PlayerIdentityPayload]) -> std::io::Result<()> {
+    let json = (!pending.is_empty()).then(|| serde_json::to_string(pending)).transpose()?;
+    write(&storage_key(game_name, "pending_player_identity"), json.as_deref())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::Duration;
+
+  use super::*;
+
+  #[test]
+  fn identity_changes_link_the_anonymous_id_once() {
+    let mut player = IndigaugePlayer::new("anonymous".to_string(), None, Vec::new());
+    assert_eq!(player.id(), "anonymous");
+
+    assert!(player.identify("account-1", true));
+    assert!(!player.identify("account-1", true));
+    assert!(player.identify("account-2", true));
+    assert_eq!(player.id(), "account-2");
+
+    assert!(player.reset("anonymous-2".to_string()));
+    assert!(!player.reset("anonymous-3".to_string()));
+    assert_eq!(player.id(), "anonymous-2");
+
+    let changes = player
+      .pending
+      .iter()
+      .map(|change| {
+        (
+          change.previous_player_id.as_str(),
+          change.player_id.as_str(),
+          change.anonymous_id.as_deref(),
+        )
+      })
+      .collect::<Vec<_>>();
+    assert_eq!(
+      changes,
+      vec![
+        ("anonymous", "account-1", Some("anonymous")),
+        ("account-1", "account-2", None),
+        ("account-2", "anonymous-2", None),
+      ]
+    );
+  }
+
+  #[test]
+  fn failed_identity_changes_are_retried_in_order() {
+    let mut player = IndigaugePlayer::new("anonymous".to_string(), None, Vec::new());
+    player.identify("account-1", true);
+    player.identify("account-2", true);
+    player.identify("account-3", true);
+    // Changes made within the same millisecond can't be ordered by time.
+    for (index, change) in player.pending.iter_mut().enumerate() {
+      change.changed_at = index as u64;
+    }
+    let newest = player.pending.pop().unwrap();
+
+    let now = Instant::now();
+    let changes = player.take_pending(now);
+    assert_eq!(changes.len(), 2);
+    assert!(player.pending.is_empty());
+    assert_eq!(player.unconfirmed(), changes);
+
+    player.pending.push(newest);
+    player.settle(&changes[0]);
+    player.requeue(changes[1].clone(), now + Duration::from_secs(5));
+    assert_eq!(
+      player.pending.iter().map(|change| change.player_id.as_str()).collect::<Vec<_>>(),
+      vec!["account-2", "account-3"]
+    );
+
+    assert!(player.take_pending(now).is_empty());
+    assert_eq!(player.take_pending(now + Duration::from_secs(5)).len(), 2);
+    assert_eq!(player.unconfirmed().len(), 2);
+  }
+}
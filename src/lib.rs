@@ -1,28 +1,68 @@
 mod api_types;
 pub(crate) mod utils;
 
+#[cfg(all(feature = "client", not(target_family = "wasm")))]
+pub mod client;
+
 pub(crate) mod config;
+pub(crate) mod delivery;
 pub(crate) mod event;
+pub(crate) mod experiments;
 pub(crate) mod feedback;
+pub(crate) mod identity;
 pub mod plugin;
+pub(crate) mod reflect;
+pub(crate) mod remote_config;
 pub(crate) mod session;
+#[cfg(not(target_family = "wasm"))]
+pub(crate) mod spool;
+pub(crate) mod transport;
+pub(crate) mod worker;
 
 #[cfg(feature = "tracing")]
 pub mod tracing;
 
+#[doc(hidden)]
+pub mod __macro_support {
+  pub use crate::event::metadata::{
+    CaptureInline, CaptureSerialize, EventMetadata, InlineMetadata, MetadataCapture, MetadataKey,
+  };
+}
+
 pub mod prelude {
-  pub use crate::config::{IndigaugeLogLevel, IndigaugeMode};
-  pub use crate::event::utils::{enqueue, validate_event_type_compile_time};
+  pub use crate::api_types::{EventGameTime, EventPayload, EventPayloadCtx};
+  pub use crate::event::metadata::{EventMetadata, InlineMetadata, MetadataValue};
+  #[cfg(all(feature = "client", not(target_family = "wasm")))]
+  pub use crate::client::{IndigaugeClient, IndigaugeClientBuilder, IndigaugeClientError};
+  pub use crate::config::{IndigaugeLogLevel, IndigaugeMode, OversizedMetadataPolicy, SessionStartRetry};
+  #[cfg(not(target_family = "wasm"))]
+  pub use crate::event::sinks::FileEventSink;
+  pub use crate::event::sinks::{EventSink, EventSinkConfig};
+  pub use crate::event::utils::{enqueue, enqueue_event, validate_event_type_compile_time};
+  pub use crate::experiments::{Experiment, ExperimentVariant, Experiments, in_variant};
   pub use crate::feedback::observers::{switch_state_on_feedback_despawn, switch_state_on_feedback_spawn};
   pub use crate::feedback::{
     resources::{FeedbackKeyCodeToggle, FeedbackPanelProps, FeedbackPanelStyles},
     types::{FeedbackCategory, FeedbackSpawnPosition},
   };
+  pub use crate::identity::{IdentifyPlayerEvent, IndigaugePlayer, ResetPlayerEvent};
   pub use crate::plugin::IndigaugePlugin;
+  pub use crate::reflect::{IndigaugeInclude, IndigaugeReflect, IndigaugeSkip, reflect_to_json};
+  pub use crate::remote_config::{
+    IndigaugeRemoteConfig, IndigaugeRemoteConfigChanged, RemoteConfigSource, RemoteSdkConfig,
+  };
   pub use crate::session::observers::switch_state_after_session_init;
-  pub use crate::session::systems::{end_session, start_default_session};
+  pub use crate::session::systems::{end_session, record_state_in_heartbeat, start_default_session};
   pub use crate::session::{
-    events::{IndigaugeInitDoneEvent, StartSessionEvent},
-    resources::EmptySessionMeta,
+    IndigaugeAppExt,
+    conditions::{ig_session_active, ig_session_ended, ig_session_failed},
+    events::{
+      EndSessionEvent, IndigaugeInitDoneEvent, IndigaugeSessionRefreshed, IndigaugeSessionStatusChanged,
+      StartSessionEvent,
+    },
+    local_session_id,
+    resources::{EmptySessionMeta, HeartbeatContext, IndigaugeActivity, IndigaugeSessionStatus},
+    types::{IdleReason, SessionEndReason},
   };
+  pub use crate::transport::{IndigaugeCompression, IndigaugeWireFormat};
 }
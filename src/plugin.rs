@@ -1,23 +1,32 @@
-use std::marker::PhantomData;
+use std::{marker::PhantomData, time::Duration};
 
 use bevy::prelude::*;
 use bevy_mod_reqwest::ReqwestPlugin;
-use crossbeam_channel::{Sender, bounded};
-use once_cell::sync::OnceCell;
 use serde::Serialize;
 
 use crate::{
   config::*,
   event::{
     EventsPlugin,
-    resources::{BufferedEvents, EventQueueReceiver, QueuedEvent},
+    resources::{BufferedEvents, EventQueueReceiver},
+    sinks::{EventSinkConfig, EventSinks},
+    utils::{GLOBAL_TX, init_event_queue},
   },
+  experiments::{Experiment, Experiments, observe_remote_config_changed, reassign_on_identity_change},
   feedback::FeedbackUiPlugin,
-  session::{SessionPlugin, resources::EmptySessionMeta},
+  identity::{
+    IdentifyPlayerEvent, IndigaugePlayer, ResetPlayerEvent, observe_identify_player_event, observe_reset_player_event,
+    report_identity_changes,
+  },
+  remote_config::{IndigaugeRemoteConfig, IndigaugeRemoteConfigChanged},
+  session::{
+    SessionPlugin,
+    resources::{EmptySessionMeta, SessionApiKey},
+  },
+  transport::{IndigaugeCompression, IndigaugeWireFormat, TransportState},
+  worker::{DeliveryWorker, dispatch_delivery_outputs},
 };
 
-pub(crate) static GLOBAL_TX: OnceCell<Sender<QueuedEvent>> = OnceCell::new();
-
 pub struct IndigaugePlugin<Meta = EmptySessionMeta> {
   public_key: String,
   /// Defaults to cargo package name
@@ -25,6 +34,24 @@ pub struct IndigaugePlugin<Meta = EmptySessionMeta> {
   game_version: String,
   log_level: IndigaugeLogLevel,
   mode: IndigaugeMode,
+  stamp_game_time: bool,
+  priority_event_types: Vec<String>,
+  priority_flush_interval: Duration,
+  priority_batch_size: usize,
+  compression: IndigaugeCompression,
+  compression_threshold: usize,
+  wire_format: IndigaugeWireFormat,
+  max_batch_bytes: usize,
+  max_event_metadata_bytes: usize,
+  oversized_metadata: OversizedMetadataPolicy,
+  session_start_retry: SessionStartRetry,
+  offline_first: bool,
+  shutdown_timeout: Duration,
+  idle_threshold: Duration,
+  idle_timeout: Option<Duration>,
+  heartbeat_interval: Duration,
+  experiments: Vec<Experiment>,
+  sinks: Vec<EventSinkConfig>,
   meta: PhantomData<Meta>,
 }
 
@@ -38,6 +65,184 @@ impl<M> IndigaugePlugin<M> {
     self.mode = mode;
     self
   }
+
+  /// Stamp each event with the Bevy frame count, `Time<Virtual>` elapsed and `Time<Fixed>` tick
+  /// at the time it is handled. Useful for correlating events with replays and simulation ticks.
+  ///
+  /// Defaults to false.
+  pub fn stamp_game_time(mut self, stamp_game_time: bool) -> Self {
+    self.stamp_game_time = stamp_game_time;
+    self
+  }
+
+  /// Event types that are flushed immediately in a small priority batch, ahead of queued events.
+  /// Error events are always prioritized.
+  ///
+  /// # Example
+  /// ```
+  /// use bevy_mod_indigauge::prelude::*;
+  ///
+  /// let plugin = IndigaugePlugin::<EmptySessionMeta>::default().priority_event_types(vec!["match.end"]);
+  /// ```
+  pub fn priority_event_types<T>(mut self, event_types: Vec<T>) -> Self
+  where
+    T: Into<String>,
+  {
+    self.priority_event_types = event_types.into_iter().map(Into::into).collect();
+    self
+  }
+
+  /// Minimum time between two priority flushes. Priority events arriving within this window are
+  /// sent with the next priority flush. When the server responds with `429 Too Many Requests`, every
+  /// batch is held back for its `Retry-After` duration instead.
+  ///
+  /// Defaults to 1 second.
+  pub fn priority_flush_interval(mut self, interval: Duration) -> Self {
+    self.priority_flush_interval = interval;
+    self
+  }
+
+  /// Maximum number of events in a priority batch. Priority events beyond it are sent with the next priority flush.
+  ///
+  /// Defaults to 8.
+  pub fn priority_batch_size(mut self, batch_size: usize) -> Self {
+    self.priority_batch_size = batch_size;
+    self
+  }
+
+  /// Compress event batches with the given compression. Requires the `gzip` or `zstd` feature.
+  /// If the server rejects a compressed batch, it is resent uncompressed and compression is
+  /// disabled for the rest of the session.
+  ///
+  /// Defaults to [`IndigaugeCompression::None`].
+  pub fn compression(mut self, compression: IndigaugeCompression) -> Self {
+    self.compression = compression;
+    self
+  }
+
+  /// Request bodies smaller than this many bytes are sent uncompressed.
+  ///
+  /// Defaults to 1024 bytes.
+  pub fn compression_threshold(mut self, bytes: usize) -> Self {
+    self.compression_threshold = bytes;
+    self
+  }
+
+  /// Serialization format for event batches. Binary formats require their cargo feature (`msgpack` or `cbor`).
+  /// If the server rejects the format, batches are resent as JSON for the rest of the session.
+  ///
+  /// Defaults to [`IndigaugeWireFormat::Json`].
+  pub fn wire_format(mut self, wire_format: IndigaugeWireFormat) -> Self {
+    self.wire_format = wire_format;
+    self
+  }
+
+  /// Maximum serialized size of an event batch in bytes. Batches are cut when either this or
+  /// the batch size is reached.
+  ///
+  /// Defaults to 512 KiB.
+  pub fn max_batch_bytes(mut self, bytes: usize) -> Self {
+    self.max_batch_bytes = bytes;
+    self
+  }
+
+  /// Maximum serialized size of a single event's metadata in bytes, and what to do with events that exceed it.
+  ///
+  /// Defaults to 16 KiB and [`OversizedMetadataPolicy::Truncate`].
+  pub fn max_event_metadata_bytes(mut self, bytes: usize, policy: OversizedMetadataPolicy) -> Self {
+    self.max_event_metadata_bytes = bytes;
+    self.oversized_metadata = policy;
+    self
+  }
+
+  /// How failed session starts are retried. Events captured while the session is being started are buffered
+  /// (up to the queue limit) and sent once it is established.
+  ///
+  /// Defaults to unlimited attempts with a backoff from 2 seconds up to 5 minutes.
+  pub fn session_start_retry(mut self, retry: SessionStartRetry) -> Self {
+    self.session_start_retry = retry;
+    self
+  }
+
+  /// Start sessions immediately with a locally generated id and clock, without waiting for the server.
+  /// `IndigaugeInitDoneEvent::Success` is triggered right away and the session is registered in the background.
+  /// If the game exits before the server could be reached, the session and its events are stored on disk and
+  /// registered on a later launch. On wasm nothing is stored, a session that isn't registered before the page is
+  /// closed is lost.
+  ///
+  /// Defaults to false.
+  pub fn offline_first(mut self, offline_first: bool) -> Self {
+    self.offline_first = offline_first;
+    self
+  }
+
+  /// How long the app may block on exit to send the remaining events, metadata and the end of the session.
+  /// Whatever isn't sent in time is stored on disk and sent on a later launch.
+  ///
+  /// Defaults to 3 seconds. On wasm, the requests are sent without blocking.
+  pub fn shutdown_timeout(mut self, timeout: Duration) -> Self {
+    self.shutdown_timeout = timeout;
+    self
+  }
+
+  /// How long the player can go without input before they are considered idle. Players are also idle while the
+  /// window is unfocused or minimized. See [`IndigaugeActivity`](crate::prelude::IndigaugeActivity).
+  ///
+  /// Defaults to 5 minutes.
+  pub fn idle_threshold(mut self, threshold: Duration) -> Self {
+    self.idle_threshold = threshold;
+    self
+  }
+
+  /// End the session with [`SessionEndReason::IdleTimeout`](crate::prelude::SessionEndReason::IdleTimeout) once the
+  /// player has been idle for this long.
+  ///
+  /// Disabled by default.
+  pub fn idle_timeout(mut self, timeout: Duration) -> Self {
+    self.idle_timeout = Some(timeout);
+    self
+  }
+
+  /// How often a heartbeat with the vitals of the game is sent while the session is active: frame rate and frame
+  /// time percentiles since the last heartbeat, process memory, active and idle time, the
+  /// [`HeartbeatContext`](crate::prelude::HeartbeatContext) and event queue stats. Independent of how often events
+  /// are flushed.
+  ///
+  /// Defaults to 30 seconds.
+  pub fn heartbeat_interval(mut self, interval: Duration) -> Self {
+    self.heartbeat_interval = interval;
+    self
+  }
+
+  /// Defines an A/B experiment the player is assigned to. An experiment of the same name in the remote config
+  /// replaces it. Can be called multiple times. See [`Experiments`].
+  ///
+  /// # Example
+  /// ```
+  /// use bevy_mod_indigauge::prelude::*;
+  ///
+  /// let plugin = IndigaugePlugin::<EmptySessionMeta>::default()
+  ///   .experiment(Experiment::new("new_tutorial").variant("a", 1).variant("b", 1));
+  /// ```
+  pub fn experiment(mut self, experiment: Experiment) -> Self {
+    self.experiments.push(experiment);
+    self
+  }
+
+  /// Registers an additional sink that receives events alongside the Indigauge API, with its own
+  /// level/event type filter and batching settings. Can be called multiple times.
+  ///
+  /// # Example
+  /// ```
+  /// use bevy_mod_indigauge::prelude::*;
+  ///
+  /// let plugin = IndigaugePlugin::<EmptySessionMeta>::default()
+  ///   .add_sink(EventSinkConfig::new(FileEventSink::new("logs/events.jsonl")).min_level(IndigaugeLogLevel::Warn));
+  /// ```
+  pub fn add_sink(mut self, sink: EventSinkConfig) -> Self {
+    self.sinks.push(sink);
+    self
+  }
 }
 
 impl<M> IndigaugePlugin<M>
@@ -65,6 +270,24 @@ where
       game_version: env!("CARGO_PKG_VERSION").to_string(),
       log_level: IndigaugeLogLevel::Info,
       mode: IndigaugeMode::default(),
+      stamp_game_time: false,
+      priority_event_types: Vec::new(),
+      priority_flush_interval: Duration::from_secs(1),
+      priority_batch_size: 8,
+      compression: IndigaugeCompression::default(),
+      compression_threshold: 1024,
+      wire_format: IndigaugeWireFormat::default(),
+      max_batch_bytes: 512 * 1024,
+      max_event_metadata_bytes: 16 * 1024,
+      oversized_metadata: OversizedMetadataPolicy::default(),
+      session_start_retry: SessionStartRetry::default(),
+      offline_first: false,
+      shutdown_timeout: Duration::from_secs(3),
+      idle_threshold: Duration::from_secs(5 * 60),
+      idle_timeout: None,
+      heartbeat_interval: Duration::from_secs(30),
+      experiments: Vec::new(),
+      sinks: Vec::new(),
       meta: PhantomData,
     }
   }
@@ -75,7 +298,23 @@ where
   M: Resource + Serialize,
 {
   fn build(&self, app: &mut App) {
-    let config = IndigaugeConfig::new(&self.game_name, &self.public_key, &self.game_version);
+    let mut config = IndigaugeConfig::new(&self.game_name, &self.public_key, &self.game_version);
+    config.stamp_game_time = self.stamp_game_time;
+    config.priority_event_types = self.priority_event_types.clone();
+    config.priority_flush_interval = self.priority_flush_interval;
+    config.priority_batch_size = self.priority_batch_size;
+    config.compression = self.compression;
+    config.compression_threshold = self.compression_threshold;
+    config.wire_format = self.wire_format;
+    config.max_batch_bytes = self.max_batch_bytes;
+    config.max_event_metadata_bytes = self.max_event_metadata_bytes;
+    config.oversized_metadata = self.oversized_metadata;
+    config.session_start_retry = self.session_start_retry.clone();
+    config.offline_first = self.offline_first;
+    config.shutdown_timeout = self.shutdown_timeout;
+    config.idle_threshold = self.idle_threshold;
+    config.idle_timeout = self.idle_timeout;
+    config.heartbeat_interval = self.heartbeat_interval;
 
     if matches!(self.mode, IndigaugeMode::Live | IndigaugeMode::Dev) {
       if config.public_key.is_empty() && self.mode == IndigaugeMode::Live {
@@ -90,23 +329,42 @@ where
             "Indigauge public key is not set for dev-mode. Logs will still be shown in the console, but not sent to the server."
           );
         }
-        let (tx, rx) = bounded::<QueuedEvent>(config.max_queue);
-        GLOBAL_TX.set(tx).ok();
-
-        app.insert_resource(EventQueueReceiver::new(rx));
+        if let Some(rx) = init_event_queue(config.max_queue) {
+          app.insert_resource(EventQueueReceiver::new(rx));
+        }
       }
     }
 
+    let remote_config = IndigaugeRemoteConfig::cached(&config);
+
+    let player = IndigaugePlayer::load(&config.game_name);
+    let experiments = Experiments::new(player.id().to_string(), self.experiments.clone(), &remote_config);
+
     app
       .add_plugins(ReqwestPlugin::default())
       .add_plugins((
         FeedbackUiPlugin,
         EventsPlugin::new(config.flush_interval),
-        SessionPlugin::<M>::new(config.flush_interval),
+        SessionPlugin::<M>::new(config.flush_interval, config.heartbeat_interval),
       ))
       .insert_resource(self.log_level.clone())
       .insert_resource(BufferedEvents::default())
+      .insert_resource(EventSinks::new(self.sinks.clone()))
+      .insert_resource(TransportState::default())
+      .insert_resource(DeliveryWorker::spawn(&self.log_level))
       .insert_resource(self.mode.clone())
-      .insert_resource(config);
+      .insert_resource(player)
+      .insert_resource(experiments)
+      .insert_resource(remote_config)
+      .add_event::<IndigaugeRemoteConfigChanged>()
+      .add_observer(observe_remote_config_changed)
+      .add_event::<IdentifyPlayerEvent>()
+      .add_event::<ResetPlayerEvent>()
+      .add_observer(observe_identify_player_event)
+      .add_observer(observe_reset_player_event)
+      .add_systems(Update, report_identity_changes.run_if(resource_exists::<SessionApiKey>))
+      .add_systems(Update, reassign_on_identity_change.run_if(resource_changed::<IndigaugePlayer>))
+      .insert_resource(config)
+      .add_systems(Last, dispatch_delivery_outputs);
   }
 }
@@ -0,0 +1,217 @@
+use bevy::{
+  ecs::system::SystemParam,
+  prelude::*,
+  reflect::{
+    NamedField, PartialReflect, ReflectRef, TypeRegistry, VariantInfo, VariantType, serde::TypedReflectSerializer,
+  },
+};
+use serde_json::{Map, Value};
+
+/// Field attribute that leaves the field out of metadata produced from a reflected value.
+///
+/// ```
+/// use bevy::prelude::*;
+/// use bevy_mod_indigauge::prelude::*;
+///
+/// #[derive(Resource, Reflect)]
+/// struct Loadout {
+///   weapon: String,
+///   #[reflect(@IndigaugeSkip)]
+///   inventory_cache: Vec<u32>,
+/// }
+/// ```
+#[derive(Reflect, Clone, Copy, Debug)]
+pub struct IndigaugeSkip;
+
+/// Field attribute that turns the fields of a struct into an allow list: if any field has it, only the fields with
+/// the attribute are included in metadata produced from a reflected value.
+#[derive(Reflect, Clone, Copy, Debug)]
+pub struct IndigaugeInclude;
+
+/// Whether any of the fields has the [`IndigaugeInclude`] attribute.
+fn is_allow_list<'a>(mut fields: impl Iterator<Item = &'a NamedField>) -> bool {
+  fields.any(NamedField::has_attribute::<IndigaugeInclude>)
+}
+
+fn is_included(field: Option<&NamedField>, allow_list: bool) -> bool {
+  let Some(field) = field else {
+    return !allow_list;
+  };
+
+  !field.has_attribute::<IndigaugeSkip>() && (!allow_list || field.has_attribute::<IndigaugeInclude>())
+}
+
+/// Converts a reflected value to JSON, without requiring `Serialize`. Structs and struct variants become objects,
+/// honoring the [`IndigaugeSkip`] and [`IndigaugeInclude`] field attributes, and enums are tagged by variant name like
+/// serde does.
+/// Values that are not structured, like numbers and strings, are serialized with the `ReflectSerialize` data of the
+/// type registry, or become `null` if their type isn't registered.
+pub fn reflect_to_json(value: &dyn PartialReflect, registry: &TypeRegistry) -> Value {
+  match value.reflect_ref() {
+    ReflectRef::Struct(value) => {
+      let info = value.get_represented_struct_info();
+      let allow_list = info.is_some_and(|info| is_allow_list(info.iter()));
+
+      let fields = (0..value.field_len()).filter_map(|index| {
+        let name = value.name_at(index)?;
+        let field = value.field_at(index)?;
+        let included = is_included(info.and_then(|info| info.field(name)), allow_list);
+        included.then(|| (name.to_string(), reflect_to_json(field, registry)))
+      });
+      Value::Object(fields.collect())
+    },
+    ReflectRef::TupleStruct(value) if value.field_len() == 1 => {
+      value.field(0).map_or(Value::Null, |field| reflect_to_json(field, registry))
+    },
+    ReflectRef::TupleStruct(value) => value.iter_fields().map(|field| reflect_to_json(field, registry)).collect(),
+    ReflectRef::Tuple(value) => value.iter_fields().map(|field| reflect_to_json(field, registry)).collect(),
+    ReflectRef::List(value) => value.iter().map(|item| reflect_to_json(item, registry)).collect(),
+    ReflectRef::Array(value) => value.iter().map(|item| reflect_to_json(item, registry)).collect(),
+    ReflectRef::Set(value) => value.iter().map(|item| reflect_to_json(item, registry)).collect(),
+    ReflectRef::Map(value) => {
+      let entries = value.iter().map(|(key, value)| {
+        let key = match reflect_to_json(key, registry) {
+          Value::String(key) => key,
+          key => key.to_string(),
+        };
+        (key, reflect_to_json(value, registry))
+      });
+      Value::Object(entries.collect())
+    },
+    ReflectRef::Enum(value) => {
+      let is_option = value
+        .get_represented_enum_info()
+        .is_some_and(|info| info.type_path().starts_with("core::option::Option"));
+
+      match value.variant_type() {
+        VariantType::Unit if is_option => Value::Null,
+        VariantType::Unit => Value::String(value.variant_name().to_string()),
+        VariantType::Tuple if is_option => {
+          value.field_at(0).map_or(Value::Null, |field| reflect_to_json(field, registry))
+        },
+        VariantType::Tuple => {
+          let fields = value.iter_fields().map(|field| reflect_to_json(field.value(), registry));
+          let fields = match value.field_len() {
+            1 => fields.collect::<Vec<_>>().pop().unwrap_or_default(),
+            _ => fields.collect(),
+          };
+          Value::Object(Map::from_iter([(value.variant_name().to_string(), fields)]))
+        },
+        VariantType::Struct => {
+          let info = match value.get_represented_enum_info().and_then(|info| info.variant(value.variant_name())) {
+            Some(VariantInfo::Struct(info)) => Some(info),
+            _ => None,
+          };
+          let allow_list = info.is_some_and(|info| is_allow_list(info.iter()));
+
+          let fields = value.iter_fields().filter_map(|field| {
+            let name = field.name()?;
+            let included = is_included(info.and_then(|info| info.field(name)), allow_list);
+            included.then(|| (name.to_string(), reflect_to_json(field.value(), registry)))
+          });
+          Value::Object(Map::from_iter([(value.variant_name().to_string(), Value::Object(fields.collect()))]))
+        },
+      }
+    },
+    _ => serde_json::to_value(TypedReflectSerializer::new(value, registry)).unwrap_or_default(),
+  }
+}
+
+/// Produces metadata from reflected values with the type registry of the app.
+///
+/// # Example
+/// ```
+/// use bevy::prelude::*;
+/// use bevy_mod_indigauge::{ig_info, prelude::*};
+///
+/// #[derive(Resource, Reflect)]
+/// struct Loadout {
+///   weapon: String,
+/// }
+///
+/// fn equip(loadout: Res<Loadout>, reflect: IndigaugeReflect) {
+///   ig_info!("loadout.changed", { "loadout": reflect.to_json(&*loadout) });
+/// }
+/// ```
+#[derive(SystemParam)]
+pub struct IndigaugeReflect<'w> {
+  registry: Res<'w, AppTypeRegistry>,
+}
+
+impl IndigaugeReflect<'_> {
+  /// Converts the value to JSON, see [`reflect_to_json`].
+  pub fn to_json(&self, value: &dyn PartialReflect) -> Value {
+    reflect_to_json(value, &self.registry.read())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serde_json::json;
+
+  #[derive(Reflect)]
+  enum Class {
+    Mage,
+    Archer {
+      range: u32,
+      #[reflect(@IndigaugeSkip)]
+      quiver: Vec<u32>,
+    },
+    Bard {
+      #[reflect(@IndigaugeInclude)]
+      instrument: String,
+      songs: Vec<String>,
+    },
+  }
+
+  #[derive(Reflect)]
+  struct Loadout {
+    weapon: String,
+    class: Class,
+    companion: Option<Class>,
+    #[reflect(@IndigaugeSkip)]
+    cache: Vec<u32>,
+  }
+
+  #[derive(Reflect)]
+  struct Settings {
+    #[reflect(@IndigaugeInclude)]
+    fov: f32,
+    #[reflect(@IndigaugeInclude)]
+    tags: Vec<String>,
+    player_name: String,
+  }
+
+  #[test]
+  fn reflected_structs_honor_skip_and_include_attributes() {
+    let registry = TypeRegistry::default();
+
+    let loadout = Loadout {
+      weapon: "bow".to_string(),
+      class: Class::Archer {
+        range: 30,
+        quiver: vec![1, 2],
+      },
+      companion: Some(Class::Mage),
+      cache: vec![1, 2, 3],
+    };
+    assert_eq!(
+      reflect_to_json(&loadout, &registry),
+      json!({ "weapon": "bow", "class": { "Archer": { "range": 30 } }, "companion": "Mage" })
+    );
+
+    let settings = Settings {
+      fov: 90.0,
+      tags: vec!["hdr".to_string()],
+      player_name: "private".to_string(),
+    };
+    assert_eq!(reflect_to_json(&settings, &registry), json!({ "fov": 90.0, "tags": ["hdr"] }));
+
+    let bard = Class::Bard {
+      instrument: "lute".to_string(),
+      songs: vec!["ballad".to_string()],
+    };
+    assert_eq!(reflect_to_json(&bard, &registry), json!({ "Bard": { "instrument": "lute" } }));
+  }
+}
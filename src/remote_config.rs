@@ -0,0 +1,351 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use serde::{Deserialize, de::DeserializeOwned};
+
+use crate::{
+  api_types::EventPayload, config::IndigaugeConfig, config::IndigaugeLogLevel, event::resources::BufferedEvents,
+  experiments::fnv1a, session::local_session_id,
+};
+
+/// Settings in the `sdk` section of the remote config that the SDK applies itself.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct RemoteSdkConfig {
+  /// Kill switch. If false, no events or heartbeats are sent to Indigauge. Event sinks still receive events.
+  pub enabled: bool,
+  /// Fraction of sessions that send events and heartbeats, between 0 and 1.
+  pub session_sample_rate: f64,
+  /// Fraction of events that are kept, per event namespace (the part of the event type before the `.`).
+  pub event_sample_rates: HashMap<String, f64>,
+  /// Event namespaces whose events are dropped.
+  pub disabled_namespaces: Vec<String>,
+  /// Whether the feedback panel can be opened.
+  pub feedback_enabled: bool,
+}
+
+impl Default for RemoteSdkConfig {
+  fn default() -> Self {
+    Self {
+      enabled: true,
+      session_sample_rate: 1.0,
+      event_sample_rates: HashMap::new(),
+      disabled_namespaces: Vec::new(),
+      feedback_enabled: true,
+    }
+  }
+}
+
+/// Where the current remote config came from.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RemoteConfigSource {
+  /// No remote config was received yet.
+  #[default]
+  None,
+  /// The config cached on disk by an earlier launch.
+  Cached,
+  /// The config returned by the server when the session was started.
+  Server,
+}
+
+/// The remote config document returned by the server when the session is started. Use it to tune the game without
+/// shipping a patch, e.g. for feature flags or balance values.
+///
+/// Until the session is started, the config cached by the last launch is used (not available on wasm). The `sdk`
+/// section is applied by the SDK itself, see [`RemoteSdkConfig`]. [`IndigaugeRemoteConfigChanged`] is sent and
+/// triggered whenever a different config is received.
+///
+/// # Example
+/// ```
+/// use bevy::prelude::*;
+/// use bevy_mod_indigauge::prelude::*;
+///
+/// fn spawn_enemy(remote_config: Res<IndigaugeRemoteConfig>) {
+///   let health = remote_config.get::<u32>("enemyHealth").unwrap_or(100);
+/// }
+/// ```
+#[derive(Resource, Clone, Debug, Default)]
+pub struct IndigaugeRemoteConfig {
+  document: serde_json::Value,
+  sdk: RemoteSdkConfig,
+  source: RemoteConfigSource,
+}
+
+impl IndigaugeRemoteConfig {
+  pub(crate) fn new(document: serde_json::Value, source: RemoteConfigSource) -> Self {
+    let sdk = document
+      .get("sdk")
+      .and_then(|sdk| serde_json::from_value(sdk.clone()).ok())
+      .unwrap_or_default();
+
+    Self { document, sdk, source }
+  }
+
+  /// The config cached by the last launch, or an empty config if there is none.
+  pub(crate) fn cached(config: &IndigaugeConfig) -> Self {
+    cache::read(config)
+      .map(|document| Self::new(document, RemoteConfigSource::Cached))
+      .unwrap_or_default()
+  }
+
+  /// The raw config document.
+  pub fn document(&self) -> &serde_json::Value {
+    &self.document
+  }
+
+  pub fn sdk(&self) -> &RemoteSdkConfig {
+    &self.sdk
+  }
+
+  pub fn source(&self) -> RemoteConfigSource {
+    self.source
+  }
+
+  /// Deserializes the value of a top-level key, or returns `None` if the key is missing or has a different type.
+  pub fn get<T>(&self, key: &str) -> Option<T>
+  where
+    T: DeserializeOwned,
+  {
+    self.document.get(key).and_then(|value| T::deserialize(value).ok())
+  }
+
+  /// Deserializes the whole document into a config type of the game.
+  pub fn deserialize<T>(&self) -> Result<T, serde_json::Error>
+  where
+    T: DeserializeOwned,
+  {
+    T::deserialize(&self.document)
+  }
+
+  /// Whether this session sends events and heartbeats: the kill switch is off and the session was sampled in.
+  pub fn is_collecting(&self) -> bool {
+    self.is_collecting_in(local_session_id())
+  }
+
+  fn is_collecting_in(&self, session_id: Option<&str>) -> bool {
+    self.sdk.enabled && session_id.is_none_or(|id| sampled_in(&[id.as_bytes()], self.sdk.session_sample_rate))
+  }
+
+  /// Whether the event is sent to Indigauge, after session sampling, disabled namespaces and event sampling.
+  pub(crate) fn accepts(&self, event: &EventPayload) -> bool {
+    self.accepts_in(local_session_id(), event)
+  }
+
+  fn accepts_in(&self, session_id: Option<&str>, event: &EventPayload) -> bool {
+    if !self.is_collecting_in(session_id) {
+      return false;
+    }
+
+    let namespace = event.event_type.split_once('.').map_or(&*event.event_type, |(namespace, _)| namespace);
+    if self.sdk.disabled_namespaces.iter().any(|disabled| disabled == namespace) {
+      return false;
+    }
+
+    self.sdk.event_sample_rates.get(namespace).is_none_or(|rate| {
+      let session_id = session_id.unwrap_or_default().as_bytes();
+      sampled_in(&[session_id, namespace.as_bytes(), &event.seq.to_le_bytes()], *rate)
+    })
+  }
+}
+
+/// Deterministic sampling decision for the key, so that the same session or event is always sampled the same way,
+/// also by other builds of the game. The FNV-1a hash is mixed with the MurmurHash3 finalizer, so that keys that only
+/// differ in their last bytes are spread over the whole range.
+fn sampled_in(key: &[&[u8]], rate: f64) -> bool {
+  if rate >= 1.0 {
+    return true;
+  }
+
+  let mut hash = fnv1a(key);
+  hash ^= hash >> 33;
+  hash = hash.wrapping_mul(0xff51_afd7_ed55_8ccd);
+  hash ^= hash >> 33;
+  hash = hash.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+  hash ^= hash >> 33;
+  (hash as f64 / u64::MAX as f64) < rate
+}
+
+/// Sent and triggered when a remote config that differs from the current one is received.
+///
+/// Can be read with an `EventReader` or observed with `app.add_observer`.
+#[derive(Event, Clone, Debug)]
+pub struct IndigaugeRemoteConfigChanged {
+  pub previous: IndigaugeRemoteConfig,
+  pub current: IndigaugeRemoteConfig,
+}
+
+/// Replaces the remote config with the one received from the server and caches it on disk. Buffered events are
+/// dropped if the session no longer collects.
+pub(crate) struct ApplyRemoteConfig(pub serde_json::Value);
+
+impl Command for ApplyRemoteConfig {
+  fn apply(self, world: &mut World) {
+    let current = IndigaugeRemoteConfig::new(self.0, RemoteConfigSource::Server);
+    let previous = world.get_resource::<IndigaugeRemoteConfig>().cloned().unwrap_or_default();
+
+    if let Some(config) = world.get_resource::<IndigaugeConfig>()
+      && let Err(error) = cache::write(config, current.document())
+      && world.get_resource::<IndigaugeLogLevel>().is_some_and(|level| *level <= IndigaugeLogLevel::Warn)
+    {
+      warn!(message = "Failed to cache remote config", ?error);
+    }
+
+    if !current.is_collecting()
+      && let Some(mut buffered_events) = world.get_resource_mut::<BufferedEvents>()
+    {
+      buffered_events.take_all();
+    }
+
+    world.insert_resource(current.clone());
+
+    if previous.document != current.document {
+      let event = IndigaugeRemoteConfigChanged { previous, current };
+      world.send_event(event.clone());
+      world.trigger(event);
+    }
+  }
+}
+
+#[cfg(not(target_family = "wasm"))]
+mod cache {
+  use std::{fs, path::PathBuf};
+
+  use crate::config::IndigaugeConfig;
+
+  fn cache_path(config: &IndigaugeConfig) -> Option<PathBuf> {
+    config.storage_dir().map(|dir| dir.join("remote_config.json"))
+  }
+
+  pub(super) fn read(config: &IndigaugeConfig) -> Option<serde_json::Value> {
+    let body = fs::read(cache_path(config)?).ok()?;
+    serde_json::from_slice(&body).ok()
+  }
+
+  pub(super) fn write(config: &IndigaugeConfig, document: &serde_json::Value) -> std::io::Result<()> {
+    let path = cache_path(config).ok_or(std::io::ErrorKind::NotFound)?;
+    if let Some(dir) = path.parent() {
+      fs::create_dir_all(dir)?;
+    }
+    fs::write(path, serde_json::to_vec(document)?)
+  }
+}
+
+#[cfg(target_family = "wasm")]
+mod cache {
+  use crate::config::IndigaugeConfig;
+
+  pub(super) fn read(_config: &IndigaugeConfig) -> Option<serde_json::Value> {
+    None
+  }
+
+  pub(super) fn write(_config: &IndigaugeConfig, _document: &serde_json::Value) -> std::io::Result<()> {
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serde_json::json;
+
+  #[test]
+  fn sdk_section_is_parsed_with_defaults() {
+    let config = IndigaugeRemoteConfig::new(
+      json!({ "enemyHealth": 120, "sdk": { "disabledNamespaces": ["debug"] } }),
+      RemoteConfigSource::Server,
+    );
+
+    assert_eq!(config.get::<u32>("enemyHealth"), Some(120));
+    assert_eq!(config.get::<String>("enemyHealth"), None);
+    assert!(config.sdk().enabled);
+    assert_eq!(config.sdk().disabled_namespaces, vec!["debug".to_string()]);
+    assert!(config.is_collecting());
+  }
+
+  fn event(event_type: &'static str, seq: u64) -> EventPayload {
+    EventPayload {
+      event_type: event_type.into(),
+      metadata: None,
+      level: "info",
+      elapsed_ms: 0,
+      timestamp: 0,
+      seq,
+      idempotency_key: None,
+      context: None,
+      game_time: None,
+    }
+  }
+
+  #[test]
+  fn sampling_is_stable_and_spread_out() {
+    let sessions = ["session-1", "session-2", "session-3", "session-4", "session-5", "session-6"];
+    let sampled = sessions.map(|session| sampled_in(&[session.as_bytes()], 0.15));
+    assert_eq!(sampled, [false, false, true, true, true, false]);
+
+    assert!(sampled_in(&[b"session-1"], 1.0));
+    assert!(!sampled_in(&[b"session-2"], 0.0));
+
+    let sampled = (0..1000_u64).filter(|seq| sampled_in(&[b"session", b"ui", &seq.to_le_bytes()], 0.25)).count();
+    assert!((200..300).contains(&sampled), "{sampled} of 1000 sampled in");
+  }
+
+  #[test]
+  fn events_are_filtered_by_namespace_and_sample_rate() {
+    let config = IndigaugeRemoteConfig::new(
+      json!({ "sdk": { "disabledNamespaces": ["debug"], "eventSampleRates": { "ui": 0.25, "combat": 0.0 } } }),
+      RemoteConfigSource::Server,
+    );
+    let session = Some("session");
+
+    assert!(config.accepts_in(session, &event("game.start", 0)));
+    assert!(!config.accepts_in(session, &event("debug.frame", 0)));
+    assert!(!config.accepts_in(session, &event("debug", 0)));
+    assert!(config.accepts_in(session, &event("debugger.attached", 0)));
+    assert!((0..100).all(|seq| !config.accepts_in(session, &event("combat.hit", seq))));
+
+    let accepted = (0..1000).filter(|seq| config.accepts_in(session, &event("ui.click", *seq))).count();
+    assert!((200..300).contains(&accepted), "{accepted} of 1000 accepted");
+
+    let disabled = IndigaugeRemoteConfig::new(json!({ "sdk": { "enabled": false } }), RemoteConfigSource::Server);
+    assert!(!disabled.accepts_in(session, &event("game.start", 0)));
+
+    let sampled_out =
+      IndigaugeRemoteConfig::new(json!({ "sdk": { "sessionSampleRate": 0.0 } }), RemoteConfigSource::Server);
+    assert!(!sampled_out.accepts_in(session, &event("game.start", 0)));
+    assert!(sampled_out.accepts_in(None, &event("game.start", 0)));
+  }
+
+  #[test]
+  fn applying_a_remote_config_replaces_it_and_drops_buffered_events_when_disabled() {
+    let storage_dir = std::env::temp_dir().join(format!("indigauge-remote-config-{}", std::process::id()));
+    let mut config = IndigaugeConfig::new("Game", "public-key", "1.0.0");
+    config.storage_dir = Some(storage_dir.clone());
+    let mut buffered_events = BufferedEvents::default();
+    buffered_events.push(crate::event::resources::QueuedEvent::new(event("game.start", 0), 0), &config);
+
+    let mut world = World::new();
+    world.insert_resource(config.clone());
+    world.insert_resource(buffered_events);
+    world.insert_resource(IndigaugeLogLevel::Error);
+    world.init_resource::<Events<IndigaugeRemoteConfigChanged>>();
+
+    ApplyRemoteConfig(json!({ "enemyHealth": 120 })).apply(&mut world);
+    assert_eq!(world.resource::<IndigaugeRemoteConfig>().source(), RemoteConfigSource::Server);
+    assert_eq!(world.resource::<BufferedEvents>().events().len(), 1);
+    assert_eq!(IndigaugeRemoteConfig::cached(&config).get::<u32>("enemyHealth"), Some(120));
+
+    ApplyRemoteConfig(json!({ "enemyHealth": 120 })).apply(&mut world);
+    ApplyRemoteConfig(json!({ "sdk": { "enabled": false } })).apply(&mut world);
+    assert!(!world.resource::<IndigaugeRemoteConfig>().is_collecting());
+    assert!(world.resource::<BufferedEvents>().is_empty());
+
+    let _ = std::fs::remove_dir_all(&storage_dir);
+
+    let changes = world.resource::<Events<IndigaugeRemoteConfigChanged>>();
+    let documents = changes.iter_current_update_events().map(|change| change.current.document().clone());
+    assert_eq!(
+      documents.collect::<Vec<_>>(),
+      vec![json!({ "enemyHealth": 120 }), json!({ "sdk": { "enabled": false } })]
+    );
+  }
+}
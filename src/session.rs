@@ -1,19 +1,43 @@
 use std::{
   marker::PhantomData,
+  sync::atomic::{AtomicU64, Ordering},
   time::{Duration, Instant},
 };
 
-use bevy::{prelude::*, time::common_conditions::on_timer, window::WindowCloseRequested};
+use bevy::{
+  input::{
+    gamepad::GamepadEvent,
+    keyboard::KeyboardInput,
+    mouse::{MouseButtonInput, MouseMotion, MouseWheel},
+    touch::TouchInput,
+  },
+  prelude::*,
+  ecs::schedule::common_conditions::on_event,
+  time::common_conditions::on_timer,
+  window::{WindowCloseRequested, WindowFocused, WindowOccluded},
+};
 use once_cell::sync::OnceCell;
 use serde::Serialize;
 
 use crate::{
-  prelude::StartSessionEvent,
-  session::observers::observe_start_session_event,
-  session::resources::{SessionApiKey, SessionMeta},
-  session::systems::{handle_exit_event, handle_updated_metadata, update_metadata},
+  prelude::{StartSessionEvent, ig_session_ended},
+  session::events::{IndigaugeSessionRefreshed, IndigaugeSessionStatusChanged},
+  session::observers::{observe_end_session_event, observe_init_done_event, observe_start_session_event},
+  session::resources::{
+    FrameTimes, HeartbeatContext, IndigaugeActivity, IndigaugeSessionStatus, MetadataSnapshot, PendingMetadata,
+    SessionApiKey, SessionMetadata, SessionStartAttempts,
+  },
+  session::systems::{
+    collect_reflect_heartbeat_context, collect_reflect_session_meta, collect_session_meta, handle_exit_event,
+    metadata_due, record_frame_time, remove_reflect_heartbeat_context, remove_session_meta, request_metadata_update,
+    retry_session_start, send_heartbeat, stage_final_metadata, track_activity, update_metadata,
+  },
 };
 
+#[cfg(not(target_family = "wasm"))]
+use crate::session::systems::{register_spooled_sessions, spool_unregistered_session};
+
+pub mod conditions;
 pub mod events;
 pub(crate) mod observers;
 pub mod resources;
@@ -22,20 +46,179 @@ pub(crate) mod types;
 pub mod utils;
 
 pub(crate) static SESSION_START_INSTANT: OnceCell<Instant> = OnceCell::new();
+static SESSION_LOCAL_ID: OnceCell<String> = OnceCell::new();
+/// The generation of the current session in the upper bits and the next sequence number in the lower bits, so that
+/// both are read and restarted atomically.
+static SESSION_EVENT_SEQ: AtomicU64 = AtomicU64::new(0);
+const EVENT_SEQ_BITS: u32 = 48;
+
+/// Returns the generation of the current session and the next sequence number for an event in it.
+pub(crate) fn next_event_seq() -> (u64, u64) {
+  let packed = SESSION_EVENT_SEQ.fetch_add(1, Ordering::Relaxed);
+  (packed >> EVENT_SEQ_BITS, packed & ((1 << EVENT_SEQ_BITS) - 1))
+}
+
+/// The generation of the current session. It changes whenever a session or a continuation session starts.
+pub(crate) fn event_seq_generation() -> u64 {
+  SESSION_EVENT_SEQ.load(Ordering::Relaxed) >> EVENT_SEQ_BITS
+}
+
+/// Restarts the sequence numbers at 0 for a new session. Events numbered for an earlier session are renumbered when
+/// they are buffered for the new one.
+pub(crate) fn restart_event_seq() {
+  let _ = SESSION_EVENT_SEQ.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |packed| {
+    Some(((packed >> EVENT_SEQ_BITS) + 1) << EVENT_SEQ_BITS)
+  });
+}
+
+/// The id generated on the client when the session was started, or `None` if no session was started yet.
+///
+/// The id is known before the session is registered with the server. Events, feedback and the session itself
+/// reference it, so that data recorded while offline can be reconciled once the server is reachable.
+pub fn local_session_id() -> Option<&'static str> {
+  SESSION_LOCAL_ID.get().map(String::as_str)
+}
+
+/// Starts accepting enqueued events and generates the local session id. Events are buffered until the session
+/// is established, and their elapsed time is measured from this point. Fails if a session was already started in
+/// this process.
+pub(crate) fn begin_capture() -> Result<(), Instant> {
+  SESSION_START_INSTANT.set(Instant::now())?;
+  init_local_session_id();
+  restart_event_seq();
+  Ok(())
+}
+
+/// Returns the local session id, generating it on first use.
+pub(crate) fn init_local_session_id() -> &'static str {
+  SESSION_LOCAL_ID.get_or_init(utils::generate_session_id)
+}
+
+/// Installs the panic hook that reports crashes to the given session.
+#[allow(unused_variables)]
+pub(crate) fn install_panic_hook(api_base: &str, session_token: &str) {
+  #[cfg(all(feature = "panic_handler", not(target_family = "wasm")))]
+  std::panic::set_hook(Box::new(utils::panic_handler(api_base.to_string(), session_token.to_string())));
+}
+
+/// Starts accepting enqueued events and installs the panic hook for an established session.
+/// Fails if a session was already started in this process.
+#[cfg(all(feature = "client", not(target_family = "wasm")))]
+pub(crate) fn begin_session(api_base: &str, session_token: &str) -> Result<(), Instant> {
+  begin_capture()?;
+  install_panic_hook(api_base, session_token);
+  Ok(())
+}
+
+/// Whether a session was started and hasn't ended yet, whether or not it is registered with the server.
+fn session_in_progress(status: Res<IndigaugeSessionStatus>) -> bool {
+  !matches!(*status, IndigaugeSessionStatus::NotStarted | IndigaugeSessionStatus::Ended)
+}
+
+/// Keeps the session metadata in sync with the metadata source `R`.
+fn metadata_source_systems<R>(key: Option<String>) -> impl IntoSystemConfigs<()>
+where
+  R: Resource + Serialize,
+{
+  (
+    // The changed condition is only evaluated once the metadata is due, so it sees every change since `R` was last
+    // serialized.
+    collect_session_meta::<R>(key.clone()).run_if(metadata_due.and(resource_exists_and_changed::<R>)),
+    remove_session_meta(key).run_if(resource_removed::<R>),
+  )
+    .after(request_metadata_update)
+    .before(stage_final_metadata)
+    .before(update_metadata)
+}
+
+/// Extends [`App`] with Indigauge settings that are owned by other plugins.
+pub trait IndigaugeAppExt {
+  /// Adds the resource `R` as a source of session metadata. When it changed, it is serialized into the `key`
+  /// sub-object of the metadata before the metadata is sent, independently of other sources. The metadata resource
+  /// passed to [`IndigaugePlugin`](crate::prelude::IndigaugePlugin) is a shorthand for a source whose fields are at
+  /// the top level.
+  ///
+  /// # Example
+  /// ```rust,ignore
+  /// app
+  ///   .add_session_meta::<GraphicsSettings>("settings")
+  ///   .add_session_meta::<Loadout>("loadout");
+  /// ```
+  fn add_session_meta<R>(&mut self, key: impl Into<String>) -> &mut Self
+  where
+    R: Resource + Serialize;
+
+  /// Like [`add_session_meta`](IndigaugeAppExt::add_session_meta), for resources that derive `Reflect` instead of
+  /// `Serialize`. Fields can be left out with [`IndigaugeSkip`](crate::prelude::IndigaugeSkip) and
+  /// [`IndigaugeInclude`](crate::prelude::IndigaugeInclude), see [`reflect_to_json`](crate::prelude::reflect_to_json).
+  fn add_reflect_session_meta<R>(&mut self, key: impl Into<String>) -> &mut Self
+  where
+    R: Resource + Reflect;
+
+  /// Keeps the fields of the reflected resource `R` in the [`HeartbeatContext`] as `key.field` entries, so that they
+  /// are sent with every heartbeat.
+  fn add_reflect_heartbeat_context<R>(&mut self, key: impl Into<String>) -> &mut Self
+  where
+    R: Resource + Reflect;
+}
+
+impl IndigaugeAppExt for App {
+  fn add_session_meta<R>(&mut self, key: impl Into<String>) -> &mut Self
+  where
+    R: Resource + Serialize,
+  {
+    self
+      .init_resource::<SessionMetadata>()
+      .add_systems(PostUpdate, metadata_source_systems::<R>(Some(key.into())))
+  }
+
+  fn add_reflect_session_meta<R>(&mut self, key: impl Into<String>) -> &mut Self
+  where
+    R: Resource + Reflect,
+  {
+    let key = key.into();
+    self.init_resource::<SessionMetadata>().add_systems(
+      PostUpdate,
+      (
+        collect_reflect_session_meta::<R>(key.clone()).run_if(metadata_due.and(resource_exists_and_changed::<R>)),
+        remove_session_meta(Some(key)).run_if(resource_removed::<R>),
+      )
+        .after(request_metadata_update)
+        .before(stage_final_metadata)
+        .before(update_metadata),
+    )
+  }
+
+  fn add_reflect_heartbeat_context<R>(&mut self, key: impl Into<String>) -> &mut Self
+  where
+    R: Resource + Reflect,
+  {
+    let key = key.into();
+    self.init_resource::<HeartbeatContext>().add_systems(
+      Update,
+      (
+        collect_reflect_heartbeat_context::<R>(key.clone()).run_if(resource_exists_and_changed::<R>),
+        remove_reflect_heartbeat_context(key).run_if(resource_removed::<R>),
+      ),
+    )
+  }
+}
 
 pub struct SessionPlugin<M: Resource + Serialize> {
   m: PhantomData<M>,
   flush_interval: Duration,
+  heartbeat_interval: Duration,
 }
 
 impl<M> SessionPlugin<M>
 where
   M: Resource + Serialize,
 {
-  pub fn new(flush_interval: Duration) -> Self {
+  pub fn new(flush_interval: Duration, heartbeat_interval: Duration) -> Self {
     Self {
       m: Default::default(),
       flush_interval,
+      heartbeat_interval,
     }
   }
 }
@@ -46,21 +229,67 @@ where
 {
   fn build(&self, app: &mut App) {
     app
-      .insert_resource(SessionMeta::<M>::default())
+      .init_resource::<SessionMetadata>()
+      .init_resource::<IndigaugeSessionStatus>()
+      .init_resource::<SessionStartAttempts>()
+      .init_resource::<PendingMetadata>()
+      .init_resource::<MetadataSnapshot>()
+      .init_resource::<IndigaugeActivity>()
+      .init_resource::<FrameTimes>()
+      .init_resource::<HeartbeatContext>()
       .add_event::<StartSessionEvent>()
+      .add_event::<IndigaugeSessionStatusChanged>()
+      .add_event::<IndigaugeSessionRefreshed>()
+      // Registered by the window and input plugins, which headless apps, e.g. with `MinimalPlugins`, don't have.
+      // Adding an event that is already registered does nothing.
+      .add_event::<WindowCloseRequested>()
+      .add_event::<WindowFocused>()
+      .add_event::<WindowOccluded>()
+      .add_event::<KeyboardInput>()
+      .add_event::<MouseButtonInput>()
+      .add_event::<MouseMotion>()
+      .add_event::<MouseWheel>()
+      .add_event::<TouchInput>()
+      .add_event::<GamepadEvent>()
       .add_observer(observe_start_session_event)
+      .add_observer(observe_init_done_event)
+      .add_observer(observe_end_session_event)
       .add_systems(
         Update,
+        (record_frame_time, send_heartbeat.run_if(on_timer(self.heartbeat_interval)))
+          .run_if(resource_exists::<SessionApiKey>.and(not(ig_session_ended()))),
+      )
+      .add_systems(
+        PostUpdate,
+        request_metadata_update.run_if(
+          on_timer(self.flush_interval)
+            .or(on_event::<AppExit>)
+            .or(on_event::<WindowCloseRequested>),
+        ),
+      )
+      .add_systems(PostUpdate, metadata_source_systems::<M>(None))
+      // Runs after the final metadata was staged, so that it isn't also sent in the background when the app exits.
+      .add_systems(PostUpdate, update_metadata.after(stage_final_metadata).run_if(metadata_due))
+      .add_systems(Update, retry_session_start.run_if(not(resource_exists::<SessionApiKey>)))
+      .add_systems(Update, track_activity.run_if(session_in_progress))
+      .add_systems(
+        PostUpdate,
         (
-          handle_updated_metadata::<M>.run_if(resource_exists_and_changed::<M>),
-          update_metadata::<M>.run_if(on_timer(self.flush_interval)),
+          stage_final_metadata,
+          handle_exit_event::<AppExit>,
+          handle_exit_event::<WindowCloseRequested>,
         )
-          .run_if(resource_exists::<SessionApiKey>),
-      )
+          .chain()
+          .run_if(resource_exists::<SessionApiKey>.and(not(ig_session_ended()))),
+      );
+
+    #[cfg(not(target_family = "wasm"))]
+    app
+      .add_systems(Update, register_spooled_sessions.run_if(resource_added::<SessionApiKey>))
       .add_systems(
         PostUpdate,
-        (handle_exit_event::<AppExit>, handle_exit_event::<WindowCloseRequested>)
-          .run_if(resource_exists::<SessionApiKey>),
+        (spool_unregistered_session::<AppExit>, spool_unregistered_session::<WindowCloseRequested>)
+          .run_if(not(resource_exists::<SessionApiKey>).and(not(ig_session_ended()))),
       );
   }
 }
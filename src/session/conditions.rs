@@ -0,0 +1,28 @@
+use bevy::prelude::*;
+
+use crate::session::resources::IndigaugeSessionStatus;
+
+/// Run condition that is true while the Indigauge session is active.
+///
+/// # Example
+/// ```rust,ignore
+/// app.add_systems(Update, track_progress.run_if(ig_session_active()));
+/// ```
+pub fn ig_session_active() -> impl FnMut(Option<Res<IndigaugeSessionStatus>>) -> bool + Clone {
+  |status: Option<Res<IndigaugeSessionStatus>>| status.is_some_and(|status| status.is_active())
+}
+
+/// Run condition that is true if the Indigauge session failed to start.
+///
+/// # Example
+/// ```rust,ignore
+/// app.add_systems(Update, show_telemetry_offline_indicator.run_if(ig_session_failed()));
+/// ```
+pub fn ig_session_failed() -> impl FnMut(Option<Res<IndigaugeSessionStatus>>) -> bool + Clone {
+  |status: Option<Res<IndigaugeSessionStatus>>| status.is_some_and(|status| status.is_failed())
+}
+
+/// Run condition that is true once the Indigauge session has ended.
+pub fn ig_session_ended() -> impl FnMut(Option<Res<IndigaugeSessionStatus>>) -> bool + Clone {
+  |status: Option<Res<IndigaugeSessionStatus>>| status.is_some_and(|status| *status == IndigaugeSessionStatus::Ended)
+}
@@ -8,6 +8,60 @@ pub enum IndigaugeInitDoneEvent {
   UnexpectedFailure(String),
 }
 
+use serde::Serialize;
+
+use crate::session::{resources::IndigaugeSessionStatus, types::SessionEndReason};
+
+/// Sent and triggered whenever [`IndigaugeSessionStatus`] changes.
+///
+/// Can be read with an `EventReader` or observed with `app.add_observer`.
+#[derive(Event, Clone, Debug)]
+pub struct IndigaugeSessionStatusChanged {
+  pub previous: IndigaugeSessionStatus,
+  pub current: IndigaugeSessionStatus,
+}
+
+impl IndigaugeSessionStatusChanged {
+  /// Whether this transition entered the given status.
+  pub fn entered(&self, status: &IndigaugeSessionStatus) -> bool {
+    self.current == *status
+  }
+}
+
+/// Sent and triggered when the server stopped accepting the session token and a continuation session, linked to
+/// the previous one, was started. Events from the rejected requests are sent again with the new session.
+///
+/// Can be read with an `EventReader` or observed with `app.add_observer`.
+#[derive(Event, Clone, Debug)]
+pub struct IndigaugeSessionRefreshed {
+  /// How many times the session has been continued so far.
+  pub continuations: u32,
+}
+
+/// Ends the session with the given reason and optional final metadata. Buffered events are flushed first.
+///
+/// ```rust,ignore
+/// let event = EndSessionEvent::new(SessionEndReason::Custom("campaign_finished".into()));
+/// commands.trigger(event.with_metadata(json!({ "ending": "good" })));
+/// ```
+#[derive(Event, Clone, Debug)]
+pub struct EndSessionEvent {
+  pub reason: SessionEndReason,
+  pub metadata: Option<serde_json::Value>,
+}
+
+impl EndSessionEvent {
+  pub fn new(reason: SessionEndReason) -> Self {
+    Self { reason, metadata: None }
+  }
+
+  /// Final metadata sent along with the end of the session.
+  pub fn with_metadata(mut self, metadata: impl Serialize) -> Self {
+    self.metadata = serde_json::to_value(metadata).ok();
+    self
+  }
+}
+
 #[derive(Event, Default, Clone)]
 pub struct StartSessionEvent {
   pub platform: Option<String>,
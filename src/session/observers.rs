@@ -1,18 +1,22 @@
 use std::{env::consts::OS, time::Instant};
 
 use bevy::{diagnostic::SystemInfo, prelude::*, render::renderer::RenderAdapterInfo, state::state::FreelyMutableState};
-use bevy_mod_reqwest::{ReqwestErrorEvent, ReqwestResponseEvent};
+use bevy_mod_reqwest::{ReqwestErrorEvent, ReqwestResponseEvent, StatusCode};
 
 use crate::{
   api_types::{ApiResponse, StartSessionPayload, StartSessionResponse},
   config::IndigaugeConfig,
   config::IndigaugeMode,
-  plugin::GLOBAL_TX,
+  event::utils::GLOBAL_TX,
+  remote_config::ApplyRemoteConfig,
   prelude::*,
-  session::SESSION_START_INSTANT,
-  session::resources::SessionApiKey,
+  session::resources::{
+    AnnounceSessionRefreshed, IndigaugeSessionStatus, SessionApiKey, SessionStartAttempts, SetSessionStatus,
+  },
+  session::{SESSION_START_INSTANT, begin_capture, install_panic_hook, local_session_id},
+  session::systems::SessionFinisher,
   session::utils::{bucket_cores, bucket_ram_gb, coarsen_cpu_name},
-  utils::BevyIndigauge,
+  utils::{BevyIndigauge, unix_timestamp_ms},
 };
 
 pub fn switch_state_after_session_init<S>(state: S) -> impl FnMut(Trigger<IndigaugeInitDoneEvent>, ResMut<NextState<S>>)
@@ -24,12 +28,46 @@ where
   }
 }
 
+/// Keeps [`IndigaugeSessionStatus`] in sync with the outcome of starting a session.
+pub(crate) fn observe_init_done_event(trigger: Trigger<IndigaugeInitDoneEvent>, mut commands: Commands) {
+  let status = match trigger.event() {
+    IndigaugeInitDoneEvent::Success => IndigaugeSessionStatus::Active,
+    IndigaugeInitDoneEvent::Failure(reason) | IndigaugeInitDoneEvent::UnexpectedFailure(reason) => {
+      IndigaugeSessionStatus::Failed(reason.clone())
+    },
+    IndigaugeInitDoneEvent::Skipped(_) => return,
+  };
+
+  commands.queue(SetSessionStatus(status));
+}
+
+/// Ends the session when an [`EndSessionEvent`] is triggered.
+pub(crate) fn observe_end_session_event(
+  trigger: Trigger<EndSessionEvent>,
+  mut finisher: SessionFinisher,
+  session_key: Option<Res<SessionApiKey>>,
+  status: Res<IndigaugeSessionStatus>,
+  log_level: Res<IndigaugeLogLevel>,
+) {
+  let Some(session_key) = session_key.filter(|_| *status != IndigaugeSessionStatus::Ended) else {
+    if *log_level <= IndigaugeLogLevel::Warn {
+      warn!("No active session to end");
+    }
+    return;
+  };
+
+  let event = trigger.event();
+  finisher.finish(&session_key, &event.reason, event.metadata.as_ref(), false);
+}
+
 pub fn observe_start_session_event(
   event: Trigger<StartSessionEvent>,
   mut ig: BevyIndigauge,
   mut cmd: Commands,
-  sys_info: Res<SystemInfo>,
-  render_info: Res<RenderAdapterInfo>,
+  mut attempts: ResMut<SessionStartAttempts>,
+  sys_info: Option<Res<SystemInfo>>,
+  render_info: Option<Res<RenderAdapterInfo>>,
+  player: Res<IndigaugePlayer>,
 ) {
   if SESSION_START_INSTANT.get().is_some() {
     if *ig.log_level <= IndigaugeLogLevel::Warn {
@@ -44,45 +82,86 @@ pub fn observe_start_session_event(
     return;
   }
 
-  match *ig.mode {
-    IndigaugeMode::Dev => {
-      let dev_response = StartSessionResponse::dev();
-      start_session(&mut cmd, dev_response, &ig.log_level, &ig.mode, &ig.config);
-      return;
-    },
-    IndigaugeMode::Disabled => {
-      cmd.trigger(IndigaugeInitDoneEvent::Skipped("Indigauge disabled".to_string()));
-      return;
-    },
-    _ => {},
+  if *ig.mode == IndigaugeMode::Disabled {
+    cmd.trigger(IndigaugeInitDoneEvent::Skipped("Indigauge disabled".to_string()));
+    return;
+  }
+
+  // Events are captured from now on, even if the session has to be retried.
+  if begin_capture().is_err() {
+    if *ig.log_level <= IndigaugeLogLevel::Warn {
+      warn!("Session already started");
+    }
+    cmd.trigger(IndigaugeInitDoneEvent::Skipped("Session already started".to_string()));
+    return;
+  }
+  *attempts = SessionStartAttempts {
+    platform: event.event().platform.clone(),
+    started_at: unix_timestamp_ms(),
+    ..Default::default()
+  };
+  cmd.queue(SetSessionStatus(IndigaugeSessionStatus::Starting));
+
+  if ig.config.offline_first && *ig.mode == IndigaugeMode::Live {
+    // The session is recorded under its local id right away and registered in the background.
+    attempts.init_done = true;
+    attempts.init_succeeded = true;
+    cmd.trigger(IndigaugeInitDoneEvent::Success);
+  }
+
+  if *ig.mode == IndigaugeMode::Dev {
+    let dev_response = StartSessionResponse::dev();
+    start_session(&mut cmd, &mut attempts, dev_response, &ig.log_level, &ig.mode, &ig.config);
+    return;
   }
 
-  #[cfg(not(target_family = "wasm"))]
-  let player_id = Some(ig.get_or_init_player_id());
+  send_start_session_request(
+    &mut ig,
+    &mut cmd,
+    &mut attempts,
+    sys_info.as_deref(),
+    render_info.as_deref(),
+    &player,
+  );
+}
 
-  #[cfg(target_family = "wasm")]
-  let player_id = None::<String>;
+pub(crate) fn send_start_session_request(
+  ig: &mut BevyIndigauge,
+  cmd: &mut Commands,
+  attempts: &mut SessionStartAttempts,
+  sys_info: Option<&SystemInfo>,
+  render_info: Option<&RenderAdapterInfo>,
+  player: &IndigaugePlayer,
+) {
+  attempts.attempts += 1;
 
-  let event = event.event();
-  let cores = sys_info.core_count.parse().map(bucket_cores).ok();
-  let memory = sys_info
-    .memory
-    .split('.')
-    .collect::<Vec<_>>()
-    .first()
-    .and_then(|m| m.parse().map(bucket_ram_gb).ok());
-  let cpu_family = coarsen_cpu_name(&sys_info.cpu);
+  // Headless apps have no renderer, and the system information is only available with its diagnostics plugin.
+  let cores = sys_info.and_then(|sys_info| sys_info.core_count.parse().map(bucket_cores).ok());
+  let memory = sys_info.and_then(|sys_info| {
+    sys_info
+      .memory
+      .split('.')
+      .collect::<Vec<_>>()
+      .first()
+      .and_then(|m| m.parse().map(bucket_ram_gb).ok())
+  });
+  let cpu_family = sys_info.and_then(|sys_info| coarsen_cpu_name(&sys_info.cpu));
 
   let payload = StartSessionPayload {
     client_version: &ig.config.game_version,
-    player_id: player_id.as_ref(),
-    platform: event.platform.as_ref(),
+    local_session_id: local_session_id(),
+    continues_session: attempts.continues_session.as_deref(),
+    started_at: attempts.started_at,
+    player_id: Some(player.id()),
+    anonymous_id: player.custom_id().map(|_| player.anonymous_id()),
+    platform: attempts.platform.as_ref(),
     os: Some(OS),
     cpu_family: cpu_family.as_ref(),
     cores,
     memory,
-    gpu: Some(&render_info.name),
+    gpu: render_info.map(|render_info| &render_info.name),
   };
+  attempts.payload = serde_json::to_value(&payload).ok();
 
   let reqwest_client = ig.build_post_request("sessions/start", &ig.config.public_key, &payload);
 
@@ -95,7 +174,14 @@ pub fn observe_start_session_event(
     },
     Err(err) => {
       error!("Failed to create session post client: {}", err);
-      cmd.trigger(IndigaugeInitDoneEvent::Failure("Failed to create session post client".to_string()));
+      session_start_failed(
+        cmd,
+        attempts,
+        &ig.config,
+        &ig.log_level,
+        IndigaugeInitDoneEvent::Failure("Failed to create session post client".to_string()),
+        false,
+      );
     },
   }
 }
@@ -103,27 +189,44 @@ pub fn observe_start_session_event(
 pub fn on_start_session_response(
   trigger: Trigger<ReqwestResponseEvent>,
   mut commands: Commands,
+  mut attempts: ResMut<SessionStartAttempts>,
   ig_config: Res<IndigaugeConfig>,
   log_level: Res<IndigaugeLogLevel>,
   mode: Res<IndigaugeMode>,
 ) {
+  let status = trigger.event().status();
+
   let Ok(response) = trigger.event().deserialize_json::<ApiResponse<StartSessionResponse>>() else {
     if *log_level <= IndigaugeLogLevel::Error {
-      error!("Failed to deserialize response");
+      error!(message = "Failed to deserialize response", ?status);
     }
-    commands.trigger(IndigaugeInitDoneEvent::UnexpectedFailure("Failed to deserialize response".to_string()));
+    session_start_failed(
+      &mut commands,
+      &mut attempts,
+      &ig_config,
+      &log_level,
+      IndigaugeInitDoneEvent::UnexpectedFailure("Failed to deserialize response".to_string()),
+      is_retryable_status(status),
+    );
     return;
   };
 
   match response {
     ApiResponse::Ok(response) => {
-      start_session(&mut commands, response, &log_level, &mode, &ig_config);
+      start_session(&mut commands, &mut attempts, response, &log_level, &mode, &ig_config);
     },
     ApiResponse::Err(error_body) => {
       if *log_level <= IndigaugeLogLevel::Error {
         error!(message = "Failed to start session", error_code = error_body.code, error_message = error_body.message);
       }
-      commands.trigger(IndigaugeInitDoneEvent::Failure("Failed to start session".to_string()));
+      session_start_failed(
+        &mut commands,
+        &mut attempts,
+        &ig_config,
+        &log_level,
+        IndigaugeInitDoneEvent::Failure("Failed to start session".to_string()),
+        is_retryable_status(status),
+      );
     },
   }
 }
@@ -131,35 +234,72 @@ pub fn on_start_session_response(
 pub fn on_start_session_error(
   trigger: Trigger<ReqwestErrorEvent>,
   mut commands: Commands,
+  mut attempts: ResMut<SessionStartAttempts>,
+  ig_config: Res<IndigaugeConfig>,
   log_level: Res<IndigaugeLogLevel>,
 ) {
   if *log_level <= IndigaugeLogLevel::Error {
     error!(message = "Create session post request failed", error = %trigger.event().0);
   }
-  commands.trigger(IndigaugeInitDoneEvent::Failure("Create session post request failed".to_string()));
+  session_start_failed(
+    &mut commands,
+    &mut attempts,
+    &ig_config,
+    &log_level,
+    IndigaugeInitDoneEvent::Failure("Create session post request failed".to_string()),
+    true,
+  );
+}
+
+/// Server errors, timeouts and rate limiting are worth retrying. Other client errors mean the request was rejected.
+pub(crate) fn is_retryable_status(status: StatusCode) -> bool {
+  !status.is_client_error() || status == StatusCode::REQUEST_TIMEOUT || status == StatusCode::TOO_MANY_REQUESTS
+}
+
+/// Marks the session as failed and schedules a retry if the failure is retryable and attempts are left.
+/// `IndigaugeInitDoneEvent` is only triggered for the first failed attempt.
+fn session_start_failed(
+  commands: &mut Commands,
+  attempts: &mut SessionStartAttempts,
+  config: &IndigaugeConfig,
+  log_level: &IndigaugeLogLevel,
+  event: IndigaugeInitDoneEvent,
+  retryable: bool,
+) {
+  let reason = match &event {
+    IndigaugeInitDoneEvent::Failure(reason) | IndigaugeInitDoneEvent::UnexpectedFailure(reason) => reason.clone(),
+    _ => String::new(),
+  };
+
+  if retryable && config.session_start_retry.allows_retry(attempts.attempts) {
+    let backoff = config.session_start_retry.backoff(attempts.attempts);
+    attempts.retry_at = Some(Instant::now() + backoff);
+
+    if *log_level <= IndigaugeLogLevel::Warn {
+      warn!(message = "Retrying session start", attempt = attempts.attempts, retry_in = ?backoff);
+    }
+  }
+
+  commands.queue(SetSessionStatus(IndigaugeSessionStatus::Failed(reason)));
+
+  if !attempts.init_done {
+    attempts.init_done = true;
+    commands.trigger(event);
+  }
 }
 
-#[allow(unused_variables)]
 fn start_session(
   commands: &mut Commands,
+  attempts: &mut SessionStartAttempts,
   response: StartSessionResponse,
   log_level: &IndigaugeLogLevel,
   mode: &IndigaugeMode,
   config: &IndigaugeConfig,
 ) {
-  let start_instant = Instant::now();
-  if let Err(set_start_instance_err) = SESSION_START_INSTANT.set(start_instant) {
-    if *log_level <= IndigaugeLogLevel::Error {
-      error!(message = "Failed to set session start instant", error = ?set_start_instance_err);
-    }
-    commands.trigger(IndigaugeInitDoneEvent::Failure("Failed to set session start instant".to_string()));
-    return;
-  }
-
   if *log_level <= IndigaugeLogLevel::Info {
     match *mode {
       IndigaugeMode::Live => {
-        info!(message = "Indigauge session started");
+        info!(message = "Indigauge session started", attempt = attempts.attempts);
       },
       IndigaugeMode::Dev => {
         info!(message = "DEVMODE: Indigauge session started");
@@ -168,16 +308,26 @@ fn start_session(
     }
   }
 
-  let key = response.session_token.clone();
+  install_panic_hook(&config.api_base, &response.session_token);
 
-  #[cfg(all(feature = "panic_handler", not(target_family = "wasm")))]
-  {
-    use crate::session::utils::panic_handler;
+  commands.insert_resource(SessionApiKey::new(response.session_token));
+  if let Some(remote_config) = response.config {
+    commands.queue(ApplyRemoteConfig(remote_config));
+  }
+  commands.queue(SetSessionStatus(IndigaugeSessionStatus::Active));
 
-    let host_origin = config.api_base.clone();
-    std::panic::set_hook(Box::new(panic_handler(host_origin, key.clone())));
+  attempts.retry_at = None;
+  if attempts.continues_session.take().is_some() {
+    attempts.continuations += 1;
+    commands.queue(AnnounceSessionRefreshed(IndigaugeSessionRefreshed {
+      continuations: attempts.continuations,
+    }));
   }
 
-  commands.insert_resource(SessionApiKey::new(key));
-  commands.trigger(IndigaugeInitDoneEvent::Success);
+  // A session that starts after failed attempts still reports its success.
+  if !attempts.init_succeeded {
+    attempts.init_done = true;
+    attempts.init_succeeded = true;
+    commands.trigger(IndigaugeInitDoneEvent::Success);
+  }
 }
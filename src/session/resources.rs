@@ -1,6 +1,20 @@
 use bevy::prelude::*;
 use serde::Serialize;
-use std::{marker::PhantomData, ops::Deref};
+
+use crate::{
+  api_types::{EventPayload, FrameTimePercentiles},
+  config::{IndigaugeConfig, IndigaugeLogLevel},
+  event::resources::{BufferedEvents, QueuedEvent},
+  session::events::{IndigaugeSessionRefreshed, IndigaugeSessionStatusChanged},
+  session::{event_seq_generation, restart_event_seq},
+  session::types::IdleReason,
+  session::utils::{json_merge_keys, json_merge_patch, json_replace_patch},
+};
+use std::{
+  collections::BTreeMap,
+  ops::Deref,
+  time::{Duration, Instant},
+};
 
 #[derive(Resource)]
 pub struct SessionApiKey {
@@ -21,23 +35,768 @@ impl Deref for SessionApiKey {
   }
 }
 
-#[derive(Resource, Serialize)]
-pub struct EmptySessionMeta;
+/// Current state of the Indigauge session.
+///
+/// Every change is announced with an [`IndigaugeSessionStatusChanged`] event. See also the
+/// [`ig_session_active`](crate::prelude::ig_session_active) and
+/// [`ig_session_failed`](crate::prelude::ig_session_failed) run conditions.
+#[derive(Resource, Clone, Debug, PartialEq, Eq, Default)]
+pub enum IndigaugeSessionStatus {
+  /// No session has been requested yet, or Indigauge is disabled.
+  #[default]
+  NotStarted,
+  /// The session start request is in flight.
+  Starting,
+  /// The session is started and events are being sent.
+  Active,
+  /// The session could not be started. Contains the reason.
+  Failed(String),
+  /// The session was ended.
+  Ended,
+}
+
+impl IndigaugeSessionStatus {
+  pub fn is_active(&self) -> bool {
+    *self == IndigaugeSessionStatus::Active
+  }
+
+  pub fn is_failed(&self) -> bool {
+    matches!(self, IndigaugeSessionStatus::Failed(_))
+  }
+
+  /// The reason the session failed to start, if it did.
+  pub fn failure_reason(&self) -> Option<&str> {
+    match self {
+      IndigaugeSessionStatus::Failed(reason) => Some(reason),
+      _ => None,
+    }
+  }
+}
+
+/// Progress of starting the session, used to retry failed attempts.
+#[derive(Resource, Default)]
+pub(crate) struct SessionStartAttempts {
+  /// Platform from the [`StartSessionEvent`](crate::prelude::StartSessionEvent).
+  pub platform: Option<String>,
+  /// UTC unix timestamp in milliseconds of the first attempt. Sent with every attempt.
+  pub started_at: u64,
+  /// Body of the last `sessions/start` request, kept to store the session on disk if it is never registered.
+  pub payload: Option<serde_json::Value>,
+  pub attempts: u32,
+  pub retry_at: Option<Instant>,
+  /// Whether `IndigaugeInitDoneEvent` was triggered. Failures are only reported for the first attempt.
+  pub init_done: bool,
+  /// Whether `IndigaugeInitDoneEvent::Success` was triggered. It is triggered once the session is started, even if an
+  /// earlier attempt failed.
+  pub init_succeeded: bool,
+  /// Token of the expired session that the next attempt continues.
+  pub continues_session: Option<String>,
+  pub continuations: u32,
+}
+
+/// Sets the session status and, if it changed, sends and triggers an [`IndigaugeSessionStatusChanged`] event.
+pub(crate) struct SetSessionStatus(pub IndigaugeSessionStatus);
+
+impl Command for SetSessionStatus {
+  fn apply(self, world: &mut World) {
+    let previous = world.get_resource::<IndigaugeSessionStatus>().cloned().unwrap_or_default();
+    if previous == self.0 {
+      return;
+    }
+
+    world.insert_resource(self.0.clone());
+
+    let event = IndigaugeSessionStatusChanged {
+      previous,
+      current: self.0,
+    };
+    world.send_event(event.clone());
+    world.trigger(event);
+  }
+}
+
+/// Sends and triggers an [`IndigaugeSessionRefreshed`] event.
+pub(crate) struct AnnounceSessionRefreshed(pub IndigaugeSessionRefreshed);
+
+impl Command for AnnounceSessionRefreshed {
+  fn apply(self, world: &mut World) {
+    world.send_event(self.0.clone());
+    world.trigger(self.0);
+  }
+}
+
+/// Handles a request that was rejected because the session token is no longer valid.
+///
+/// The events of the rejected request are buffered again. If the token belongs to the current session, the
+/// session key is removed and a continuation session is started right away, see
+/// [`retry_session_start`](crate::session::systems::retry_session_start). Events are buffered until it is.
+pub(crate) struct ExpireSession {
+  pub session_token: String,
+  pub events: Vec<EventPayload>,
+}
+
+impl ExpireSession {
+  pub fn new(session_token: impl Into<String>) -> Self {
+    Self {
+      session_token: session_token.into(),
+      events: Vec::new(),
+    }
+  }
+}
+
+impl Command for ExpireSession {
+  fn apply(self, world: &mut World) {
+    let continues = world.get_resource::<SessionApiKey>().is_some_and(|key| **key == self.session_token);
+    if continues {
+      restart_event_seq();
+    }
+
+    // Events from the rejected requests are moved to the current session and numbered before the events buffered
+    // for it.
+    world.resource_scope(|world, mut buffered_events: Mut<BufferedEvents>| {
+      let config = world.resource::<IndigaugeConfig>();
+      for payload in self.events {
+        let mut event = QueuedEvent::new(payload, event_seq_generation());
+        event.resequence();
+        event.measure();
+        buffered_events.push(event, config);
+      }
+      buffered_events.resequence();
+    });
+
+    if !continues {
+      return;
+    }
+
+    if *world.resource::<IndigaugeLogLevel>() <= IndigaugeLogLevel::Warn {
+      warn!("Indigauge session token was rejected, starting a continuation session");
+    }
+
+    world.remove_resource::<SessionApiKey>();
+
+    let mut attempts = world.resource_mut::<SessionStartAttempts>();
+    attempts.continues_session = Some(self.session_token);
+    attempts.attempts = 0;
+    attempts.retry_at = Some(Instant::now());
+
+    SetSessionStatus(IndigaugeSessionStatus::Starting).apply(world);
+  }
+}
+
+/// Active and idle time of the current session.
+///
+/// The player is idle while the window is unfocused or minimized, or when there was no input for longer than the
+/// idle threshold. Idle periods are reported as `session.idle` and `session.resumed` events, and the totals are sent
+/// with heartbeats and the end of the session.
+#[derive(Resource, Debug)]
+pub struct IndigaugeActivity {
+  active: Duration,
+  idle: Duration,
+  focused: bool,
+  minimized: bool,
+  /// Real time elapsed since startup at the last input.
+  last_input: Duration,
+  /// Why the player is idle, and the real time elapsed since startup when it started.
+  idle_since: Option<(IdleReason, Duration)>,
+}
+
+impl Default for IndigaugeActivity {
+  fn default() -> Self {
+    Self {
+      active: Duration::ZERO,
+      idle: Duration::ZERO,
+      focused: true,
+      minimized: false,
+      last_input: Duration::ZERO,
+      idle_since: None,
+    }
+  }
+}
+
+/// A change between active and idle, returned by [`IndigaugeActivity::update`].
+#[derive(Debug, PartialEq)]
+pub(crate) enum ActivityChange {
+  Idle(IdleReason),
+  /// Contains how long the player was idle.
+  Resumed(Duration),
+}
+
+impl IndigaugeActivity {
+  /// Time the player was active.
+  pub fn active_time(&self) -> Duration {
+    self.active
+  }
+
+  /// Time the player was idle.
+  pub fn idle_time(&self) -> Duration {
+    self.idle
+  }
+
+  /// Why the player is idle, or `None` if the player is active.
+  pub fn idle_reason(&self) -> Option<IdleReason> {
+    self.idle_since.map(|(reason, _)| reason)
+  }
+
+  pub fn is_idle(&self) -> bool {
+    self.idle_since.is_some()
+  }
+
+  /// How long the current idle period has lasted, at real time `now`.
+  pub(crate) fn idle_for(&self, now: Duration) -> Option<Duration> {
+    self.idle_since.map(|(_, since)| now.saturating_sub(since))
+  }
+
+  pub(crate) fn set_focused(&mut self, focused: bool) {
+    self.focused = focused;
+  }
+
+  pub(crate) fn set_minimized(&mut self, minimized: bool) {
+    self.minimized = minimized;
+  }
+
+  pub(crate) fn record_input(&mut self, now: Duration) {
+    self.last_input = now;
+  }
+
+  /// Adds the time since the last update to the active or idle time and returns whether the player became idle or
+  /// resumed. `now` is the real time elapsed since startup.
+  pub(crate) fn update(&mut self, now: Duration, delta: Duration, idle_threshold: Duration) -> Option<ActivityChange> {
+    if self.is_idle() {
+      self.idle += delta;
+    } else {
+      self.active += delta;
+    }
 
+    let reason = if self.minimized {
+      Some(IdleReason::Minimized)
+    } else if !self.focused {
+      Some(IdleReason::Unfocused)
+    } else if now.saturating_sub(self.last_input) >= idle_threshold {
+      Some(IdleReason::Inactive)
+    } else {
+      None
+    };
+
+    match (self.idle_since, reason) {
+      (None, Some(reason)) => {
+        self.idle_since = Some((reason, now));
+        Some(ActivityChange::Idle(reason))
+      },
+      (Some((_, since)), None) => {
+        self.idle_since = None;
+        Some(ActivityChange::Resumed(now.saturating_sub(since)))
+      },
+      _ => None,
+    }
+  }
+}
+
+/// Metadata that changed but wasn't sent yet when the session is ended.
+#[derive(Resource, Default, Deref, DerefMut)]
+pub(crate) struct PendingMetadata(Option<serde_json::Value>);
+
+/// An update of the session's metadata, to record the server's response to it in the [`MetadataSnapshot`].
+pub(crate) struct MetadataUpdate {
+  session_token: String,
+  id: u64,
+  metadata: serde_json::Value,
+}
+
+/// What the server has of the session's metadata.
+///
+/// Once the server accepted every update, the next update is sent as a JSON merge patch against the accepted
+/// metadata, see [`json_merge_patch`]. While an update is in flight or after one failed, the server may or may not
+/// have applied it, so the full metadata is sent instead, with `null` for every key an earlier update set that is no
+/// longer there, see [`json_replace_patch`].
+#[derive(Resource, Default)]
+pub(crate) struct MetadataSnapshot {
+  session_token: Option<String>,
+  /// Metadata of the last update the server accepted.
+  accepted: Option<serde_json::Value>,
+  accepted_update: u64,
+  /// Every key that was sent for the session, whether or not the server applied it.
+  sent_keys: serde_json::Value,
+  sent_updates: u64,
+  /// Responses to updates up to this one are ignored, one of them failed.
+  failed_update: u64,
+  /// Set after an update failed. The full metadata is sent with the next update.
+  pub(crate) resync: bool,
+}
+
+impl MetadataSnapshot {
+  /// The body for updating the session's metadata to `metadata`, or `None` if it is unchanged.
+  pub(crate) fn patch(&self, session_token: &str, metadata: &serde_json::Value) -> Option<serde_json::Value> {
+    if self.session_token.as_deref() != Some(session_token) {
+      return Some(metadata.clone());
+    }
+
+    match &self.accepted {
+      Some(accepted) if self.accepted_update == self.sent_updates => json_merge_patch(accepted, metadata),
+      _ => Some(json_replace_patch(&self.sent_keys, metadata)),
+    }
+  }
+
+  /// Records that an update to `metadata` was sent. The server's response is recorded with [`Self::accepted`] or
+  /// [`Self::rejected`].
+  pub(crate) fn sent(&mut self, session_token: &str, metadata: serde_json::Value) -> MetadataUpdate {
+    if self.session_token.as_deref() != Some(session_token) {
+      *self = Self {
+        session_token: Some(session_token.to_string()),
+        resync: self.resync,
+        ..Default::default()
+      };
+    }
+
+    self.sent_updates += 1;
+    json_merge_keys(&mut self.sent_keys, &metadata);
+    MetadataUpdate {
+      session_token: session_token.to_string(),
+      id: self.sent_updates,
+      metadata,
+    }
+  }
+
+  pub(crate) fn accepted(&mut self, update: MetadataUpdate) {
+    if self.session_token.as_deref() != Some(&update.session_token)
+      || update.id <= self.accepted_update
+      || update.id <= self.failed_update
+    {
+      return;
+    }
+
+    if update.id == self.sent_updates {
+      // The server has exactly this metadata, keys that were removed earlier are gone.
+      self.sent_keys = update.metadata.clone();
+    }
+    self.accepted = Some(update.metadata);
+    self.accepted_update = update.id;
+  }
+
+  pub(crate) fn rejected(&mut self, update: &MetadataUpdate) {
+    if self.session_token.as_deref() != Some(&update.session_token) {
+      return;
+    }
+
+    self.accepted = None;
+    self.failed_update = self.failed_update.max(update.id);
+    self.resync = true;
+  }
+}
+
+/// Width of a frame time histogram bucket: 0.1 ms.
+const FRAME_TIME_BUCKET_NANOS: u128 = 100_000;
+/// Frame times up to 250 ms get their own bucket. Longer frames share the last bucket.
+const FRAME_TIME_BUCKETS: usize = 2500;
+
+/// Frame times recorded since the last heartbeat, as a histogram with 0.1 ms buckets, so that the memory used doesn't
+/// grow with the heartbeat interval or frame rate.
 #[derive(Resource)]
-pub struct SessionMeta<T: Resource + Serialize> {
-  pub(crate) is_changed: bool,
-  data: PhantomData<T>,
+pub(crate) struct FrameTimes {
+  buckets: Vec<u32>,
+  frames: u32,
+  total_ms: f64,
+  max_ms: f32,
 }
 
-impl<T> Default for SessionMeta<T>
-where
-  T: Resource + Serialize,
-{
+impl Default for FrameTimes {
   fn default() -> Self {
     Self {
-      is_changed: false,
-      data: PhantomData,
+      buckets: vec![0; FRAME_TIME_BUCKETS],
+      frames: 0,
+      total_ms: 0.0,
+      max_ms: 0.0,
+    }
+  }
+}
+
+impl FrameTimes {
+  pub(crate) fn record(&mut self, delta: Duration) {
+    if delta.is_zero() {
+      return;
+    }
+
+    let bucket = (delta.as_nanos() / FRAME_TIME_BUCKET_NANOS).min(FRAME_TIME_BUCKETS as u128 - 1) as usize;
+    let millis = delta.as_nanos() as f32 / 1_000_000.0;
+    self.buckets[bucket] += 1;
+    self.frames += 1;
+    self.total_ms += f64::from(millis);
+    self.max_ms = self.max_ms.max(millis);
+  }
+
+  /// Average frame rate and frame time percentiles of the recorded frames, or `None` if no frames were recorded.
+  /// Percentiles are rounded down to the bucket width, and reported as the longest frame if they fall into the last
+  /// bucket. Clears the recorded frames.
+  pub(crate) fn take_stats(&mut self) -> Option<(f32, FrameTimePercentiles)> {
+    if self.frames == 0 {
+      return None;
+    }
+
+    // Nearest-rank percentile.
+    let percentile = |p: f32| {
+      let rank = ((p * self.frames as f32).ceil() as u32).max(1);
+      let mut frames = 0;
+      let bucket = self
+        .buckets
+        .iter()
+        .position(|count| {
+          frames += count;
+          frames >= rank
+        })
+        .unwrap_or(FRAME_TIME_BUCKETS - 1);
+
+      if bucket == FRAME_TIME_BUCKETS - 1 {
+        self.max_ms
+      } else {
+        (bucket as f32 / (1_000_000 / FRAME_TIME_BUCKET_NANOS) as f32).min(self.max_ms)
+      }
+    };
+
+    let stats = (
+      (f64::from(self.frames) * 1000.0 / self.total_ms) as f32,
+      FrameTimePercentiles {
+        p50: percentile(0.5),
+        p95: percentile(0.95),
+        p99: percentile(0.99),
+        max: self.max_ms,
+      },
+    );
+
+    self.buckets.fill(0);
+    self.frames = 0;
+    self.total_ms = 0.0;
+    self.max_ms = 0.0;
+    Some(stats)
+  }
+}
+
+/// Context about the current state of the game that is sent with every heartbeat, e.g. the current screen, level or
+/// game mode. Use [`record_state_in_heartbeat`](crate::prelude::record_state_in_heartbeat) to keep a Bevy state in
+/// the context.
+///
+/// # Example
+/// ```
+/// use bevy::prelude::*;
+/// use bevy_mod_indigauge::prelude::*;
+///
+/// fn enter_level(mut context: ResMut<HeartbeatContext>) {
+///   context.set("level", "forest-2");
+/// }
+/// ```
+#[derive(Resource, Default, Clone, Debug)]
+pub struct HeartbeatContext(BTreeMap<String, String>);
+
+impl HeartbeatContext {
+  pub fn set(&mut self, key: impl Into<String>, value: impl Into<String>) {
+    self.0.insert(key.into(), value.into());
+  }
+
+  pub fn remove(&mut self, key: &str) -> Option<String> {
+    self.0.remove(key)
+  }
+
+  pub fn get(&self, key: &str) -> Option<&str> {
+    self.0.get(key).map(String::as_str)
+  }
+
+  /// Removes the entries of a reflected source: `key` itself and the `key.field` entries, except the ones in `keep`.
+  pub(crate) fn remove_source(&mut self, key: &str, keep: &[String]) {
+    self.0.retain(|entry, _| {
+      let is_source = entry.strip_prefix(key).is_some_and(|field| field.is_empty() || field.starts_with('.'));
+      !is_source || keep.contains(entry)
+    });
+  }
+
+  pub(crate) fn entries(&self) -> &BTreeMap<String, String> {
+    &self.0
+  }
+}
+
+#[derive(Resource, Serialize)]
+pub struct EmptySessionMeta;
+
+/// The session metadata, assembled from the metadata resource of the plugin and the sources added with
+/// [`add_session_meta`](crate::prelude::IndigaugeAppExt::add_session_meta). Sources that changed are only serialized
+/// when the metadata is sent, not every time they change.
+#[derive(Resource, Default)]
+pub(crate) struct SessionMetadata {
+  /// The metadata resource of the plugin, whose fields are at the top level.
+  root: Option<serde_json::Value>,
+  /// Sub-objects of the sources, by key.
+  sources: serde_json::Map<String, serde_json::Value>,
+  pub(crate) is_changed: bool,
+  /// Set when the metadata is sent this frame, on the flush interval or when the app exits. The sources that changed
+  /// since they were last serialized are serialized before it is sent.
+  pub(crate) is_due: bool,
+}
+
+impl SessionMetadata {
+  /// Sets the value of the source with the given key, or of the plugin's metadata resource if there is no key.
+  pub(crate) fn set(&mut self, key: Option<&str>, value: serde_json::Value) {
+    match key {
+      Some(key) => {
+        self.sources.insert(key.to_string(), value);
+      },
+      None => self.root = Some(value),
+    }
+    self.is_changed = true;
+  }
+
+  pub(crate) fn remove(&mut self, key: Option<&str>) {
+    match key {
+      Some(key) => {
+        self.sources.remove(key);
+      },
+      None => self.root = None,
+    }
+    self.is_changed = true;
+  }
+
+  /// The metadata document, or `None` if there is no metadata. Sources override top-level fields of the same name.
+  pub(crate) fn document(&self) -> Option<serde_json::Value> {
+    if self.sources.is_empty() {
+      return self.root.clone();
     }
+
+    let mut document = match &self.root {
+      Some(serde_json::Value::Object(root)) => root.clone(),
+      _ => serde_json::Map::new(),
+    };
+    document.extend(self.sources.clone());
+    Some(serde_json::Value::Object(document))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serde_json::json;
+
+  #[test]
+  fn metadata_is_patched_against_the_last_accepted_metadata() {
+    let mut snapshot = MetadataSnapshot::default();
+    let a = json!({ "level": 1, "mode": "story" });
+    let b = json!({ "level": 2, "mode": "story" });
+
+    assert_eq!(snapshot.patch("token", &a), Some(a.clone()));
+    let update = snapshot.sent("token", a.clone());
+    // Sent in full until the server accepted the first update.
+    assert_eq!(snapshot.patch("token", &a), Some(a.clone()));
+
+    snapshot.accepted(update);
+    assert_eq!(snapshot.patch("token", &a), None);
+    assert_eq!(snapshot.patch("token", &b), Some(json!({ "level": 2 })));
+
+    // Reverting while the update is still in flight sets every value again.
+    let update = snapshot.sent("token", b.clone());
+    assert_eq!(snapshot.patch("token", &a), Some(a.clone()));
+    snapshot.accepted(update);
+    assert_eq!(snapshot.patch("token", &a), Some(json!({ "level": 1 })));
+  }
+
+  #[test]
+  fn metadata_is_sent_in_full_with_removed_keys_after_a_rejection_or_for_a_new_session() {
+    let mut snapshot = MetadataSnapshot::default();
+    let first = snapshot.sent("token", json!({ "level": 1 }));
+    snapshot.accepted(first);
+
+    let metadata = json!({ "level": 1, "boss": { "name": "dragon", "phase": 2 } });
+    let failed = snapshot.sent("token", metadata);
+    assert_eq!(snapshot.patch("other-token", &json!({ "level": 1 })), Some(json!({ "level": 1 })));
+
+    snapshot.rejected(&failed);
+    assert!(snapshot.resync);
+    // The failed update may have been applied, so the keys it added are removed.
+    let metadata = json!({ "level": 2, "boss": { "name": "dragon" } });
+    assert_eq!(
+      snapshot.patch("token", &metadata),
+      Some(json!({ "level": 2, "boss": { "name": "dragon", "phase": null } }))
+    );
+
+    // A late response to the failed update doesn't count as accepted.
+    snapshot.accepted(failed);
+    snapshot.resync = false;
+    let resync = snapshot.sent("token", metadata.clone());
+    snapshot.accepted(resync);
+    assert_eq!(snapshot.patch("token", &metadata), None);
+  }
+
+  #[test]
+  fn status_changes_are_announced_once() {
+    let mut app = App::new();
+    app
+      .init_resource::<IndigaugeSessionStatus>()
+      .add_event::<IndigaugeSessionStatusChanged>();
+
+    let world = app.world_mut();
+    SetSessionStatus(IndigaugeSessionStatus::Starting).apply(world);
+    SetSessionStatus(IndigaugeSessionStatus::Starting).apply(world);
+    SetSessionStatus(IndigaugeSessionStatus::Failed("offline".to_string())).apply(world);
+
+    let status = world.resource::<IndigaugeSessionStatus>();
+    assert!(status.is_failed());
+    assert_eq!(status.failure_reason(), Some("offline"));
+
+    let events = world.resource::<Events<IndigaugeSessionStatusChanged>>();
+    let transitions = events
+      .iter_current_update_events()
+      .map(|event| (event.previous.clone(), event.current.clone()))
+      .collect::<Vec<_>>();
+
+    assert_eq!(
+      transitions,
+      vec![
+        (IndigaugeSessionStatus::NotStarted, IndigaugeSessionStatus::Starting),
+        (IndigaugeSessionStatus::Starting, IndigaugeSessionStatus::Failed("offline".to_string())),
+      ]
+    );
+  }
+
+  #[test]
+  fn rejected_session_tokens_start_a_continuation() {
+    let mut app = App::new();
+    app
+      .init_resource::<IndigaugeSessionStatus>()
+      .init_resource::<SessionStartAttempts>()
+      .init_resource::<BufferedEvents>()
+      .insert_resource(IndigaugeConfig::new("game", "key", "1.0.0"))
+      .insert_resource(IndigaugeLogLevel::Error)
+      .insert_resource(SessionApiKey::new("expired"))
+      .add_event::<IndigaugeSessionStatusChanged>();
+
+    let event = EventPayload {
+      event_type: "game.start".into(),
+      metadata: None,
+      level: "info",
+      elapsed_ms: 0,
+      timestamp: 0,
+      seq: 0,
+      idempotency_key: None,
+      context: None,
+      game_time: None,
+    };
+
+    // Buffered for the rejected session, with a sequence number of its generation.
+    let buffered = EventPayload {
+      event_type: "game.tick".into(),
+      seq: 5,
+      ..event.clone()
+    };
+    let world = app.world_mut();
+    world.resource_scope(|world, mut buffered_events: Mut<BufferedEvents>| {
+      buffered_events.push(QueuedEvent::new(buffered, event_seq_generation()), world.resource::<IndigaugeConfig>());
+    });
+
+    ExpireSession {
+      session_token: "expired".to_string(),
+      events: vec![event],
+    }
+    .apply(world);
+    // Responses to other requests sent with the same token don't start another continuation.
+    ExpireSession::new("expired").apply(world);
+
+    assert!(world.get_resource::<SessionApiKey>().is_none());
+    let events = world.resource::<BufferedEvents>().events();
+    assert_eq!(events.len(), 2);
+    // The rejected event is older, so it is numbered first in the continuation session.
+    assert_eq!(events[1].event_type(), "game.start");
+    assert!(events[1].payload().seq < events[0].payload().seq);
+    assert_eq!(*world.resource::<IndigaugeSessionStatus>(), IndigaugeSessionStatus::Starting);
+
+    let attempts = world.resource::<SessionStartAttempts>();
+    assert_eq!(attempts.continues_session.as_deref(), Some("expired"));
+    assert!(attempts.retry_at.is_some());
+  }
+
+  #[test]
+  fn activity_is_split_into_active_and_idle_time() {
+    let threshold = Duration::from_secs(60);
+    let mut activity = IndigaugeActivity::default();
+
+    assert_eq!(activity.update(Duration::from_secs(30), Duration::from_secs(30), threshold), None);
+
+    activity.set_focused(false);
+    assert_eq!(
+      activity.update(Duration::from_secs(40), Duration::from_secs(10), threshold),
+      Some(ActivityChange::Idle(IdleReason::Unfocused))
+    );
+    assert_eq!(activity.update(Duration::from_secs(100), Duration::from_secs(60), threshold), None);
+
+    activity.set_focused(true);
+    activity.record_input(Duration::from_secs(100));
+    assert_eq!(
+      activity.update(Duration::from_secs(101), Duration::from_secs(1), threshold),
+      Some(ActivityChange::Resumed(Duration::from_secs(61)))
+    );
+
+    assert_eq!(
+      activity.update(Duration::from_secs(161), Duration::from_secs(60), threshold),
+      Some(ActivityChange::Idle(IdleReason::Inactive))
+    );
+
+    assert_eq!(activity.active_time(), Duration::from_secs(100));
+    assert_eq!(activity.idle_time(), Duration::from_secs(61));
+  }
+
+  #[test]
+  fn frame_times_are_summarized_per_heartbeat() {
+    let mut frame_times = FrameTimes::default();
+    assert_eq!(frame_times.take_stats(), None);
+
+    for millis in 1..=100 {
+      frame_times.record(Duration::from_millis(millis));
+    }
+
+    let (fps, percentiles) = frame_times.take_stats().unwrap();
+    assert!((fps - 1000.0 / 50.5).abs() < 0.01);
+    assert_eq!(
+      percentiles,
+      FrameTimePercentiles {
+        p50: 50.0,
+        p95: 95.0,
+        p99: 99.0,
+        max: 100.0,
+      }
+    );
+    assert_eq!(frame_times.take_stats(), None);
+  }
+
+  #[test]
+  fn long_frames_share_the_last_frame_time_bucket() {
+    let mut frame_times = FrameTimes::default();
+    for _ in 0..98 {
+      frame_times.record(Duration::from_micros(16_650));
+    }
+    frame_times.record(Duration::from_millis(400));
+    frame_times.record(Duration::from_millis(900));
+
+    let (_, percentiles) = frame_times.take_stats().unwrap();
+    assert_eq!(frame_times.buckets.len(), FRAME_TIME_BUCKETS);
+    assert_eq!(
+      percentiles,
+      FrameTimePercentiles {
+        p50: 16.6,
+        p95: 16.6,
+        p99: 900.0,
+        max: 900.0,
+      }
+    );
+  }
+
+  #[test]
+  fn metadata_sources_are_merged_into_one_document() {
+    let mut metadata = SessionMetadata::default();
+    assert_eq!(metadata.document(), None);
+
+    metadata.set(None, serde_json::json!({ "difficulty": "hard", "loadout": "old" }));
+    metadata.set(Some("settings"), serde_json::json!({ "fov": 90 }));
+    metadata.set(Some("loadout"), serde_json::json!({ "weapon": "bow" }));
+    assert_eq!(
+      metadata.document(),
+      Some(serde_json::json!({ "difficulty": "hard", "settings": { "fov": 90 }, "loadout": { "weapon": "bow" } }))
+    );
+
+    metadata.remove(None);
+    metadata.remove(Some("settings"));
+    assert_eq!(metadata.document(), Some(serde_json::json!({ "loadout": { "weapon": "bow" } })));
   }
 }
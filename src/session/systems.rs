@@ -1,19 +1,65 @@
-use bevy::prelude::*;
+use std::time::Instant;
+
+use bevy::{
+  diagnostic::SystemInfo,
+  ecs::system::SystemParam,
+  input::{
+    gamepad::GamepadEvent,
+    keyboard::KeyboardInput,
+    mouse::{MouseButtonInput, MouseMotion, MouseWheel},
+    touch::TouchInput,
+  },
+  prelude::*,
+  render::renderer::RenderAdapterInfo,
+  window::{WindowCloseRequested, WindowFocused, WindowOccluded},
+};
 use serde::Serialize;
-use serde_json::json;
 
 use crate::{
-  prelude::StartSessionEvent,
-  session::resources::{SessionApiKey, SessionMeta},
+  api_types::{BatchEventPayload, EndSessionPayload, HeartbeatPayload, HeartbeatQueueStats},
+  config::{IndigaugeConfig, IndigaugeMode},
+  event::sinks::EventSinks,
+  prelude::*,
+  session::observers::send_start_session_request,
+  session::resources::{
+    ActivityChange, FrameTimes, HeartbeatContext, IndigaugeActivity, IndigaugeSessionStatus, MetadataSnapshot,
+    PendingMetadata, SessionApiKey, SessionMetadata, SessionStartAttempts, SetSessionStatus,
+  },
+  session::utils::ProcessMemory,
   utils::BevyIndigauge,
+  worker::DeliveryJob,
 };
 
-pub fn handle_exit_event<E>(exit_events: EventReader<E>, ig: BevyIndigauge, session_key: Res<SessionApiKey>)
-where
+#[cfg(not(target_family = "wasm"))]
+use crate::{
+  session::local_session_id,
+  spool::{self, SpooledSession},
+  transport::{IndigaugeCompression, IndigaugeWireFormat, encode_body},
+  utils::unix_timestamp_ms,
+  worker::DeliveryOutput,
+};
+#[cfg(not(target_family = "wasm"))]
+use bevy_mod_reqwest::reqwest::Method;
+
+/// Ends the session when `E` is sent, with the reason mapped from the event. See [`SessionEndReason`].
+///
+/// The app is about to exit, so the remaining data is sent blocking, bounded by the shutdown timeout.
+pub fn handle_exit_event<E>(
+  mut exit_events: EventReader<E>,
+  mut finisher: SessionFinisher,
+  session_key: Res<SessionApiKey>,
+  status: Res<IndigaugeSessionStatus>,
+) where
   E: Event + std::fmt::Debug,
+  for<'a> SessionEndReason: From<&'a E>,
 {
-  if !exit_events.is_empty() {
-    end_session(ig, session_key);
+  let Some(reason) = exit_events.read().last().map(SessionEndReason::from) else {
+    return;
+  };
+
+  // Closing the window also exits the app in the same frame. The session is only ended for the first of them.
+  if *status != IndigaugeSessionStatus::Ended {
+    finisher.finish(&session_key, &reason, None, true);
   }
 }
 
@@ -80,34 +126,617 @@ pub fn start_default_session(mut commands: Commands) {
 ///     .run();
 /// }
 /// ```
-pub fn end_session(mut ig: BevyIndigauge, session_key: Res<SessionApiKey>) {
-  ig.flush_events(&session_key);
+///
+/// The session is ended with [`SessionEndReason::Ended`]. To end it with another reason or final metadata, trigger an
+/// [`EndSessionEvent`] instead.
+pub fn end_session(mut finisher: SessionFinisher, session_key: Res<SessionApiKey>) {
+  finisher.finish(&session_key, &SessionEndReason::Ended, None, false);
+}
+
+/// Flushes the remaining events and metadata and ends the session.
+#[derive(SystemParam)]
+pub struct SessionFinisher<'w, 's> {
+  commands: Commands<'w, 's>,
+  ig: BevyIndigauge<'w, 's>,
+  sinks: ResMut<'w, EventSinks>,
+  pending_metadata: ResMut<'w, PendingMetadata>,
+  metadata_snapshot: ResMut<'w, MetadataSnapshot>,
+  start_attempts: Res<'w, SessionStartAttempts>,
+  activity: Option<Res<'w, IndigaugeActivity>>,
+}
+
+impl SessionFinisher<'_, '_> {
+  /// Ends the session. If the app is `exiting`, the requests are sent blocking, bounded by the shutdown timeout,
+  /// and whatever isn't sent in time is stored on disk and sent on a later launch.
+  pub(crate) fn finish(
+    &mut self,
+    session_key: &str,
+    reason: &SessionEndReason,
+    metadata: Option<&serde_json::Value>,
+    exiting: bool,
+  ) {
+    let payload = EndSessionPayload::new(reason, metadata).with_activity(self.activity.as_deref());
+
+    if exiting && *self.ig.mode == IndigaugeMode::Live {
+      self.finish_blocking(session_key, &payload);
+    } else {
+      self.finish_async(session_key, &payload);
+    }
+
+    self.sinks.flush_all_blocking();
+    self.commands.queue(SetSessionStatus(IndigaugeSessionStatus::Ended));
+  }
+
+  #[allow(clippy::collapsible_match)]
+  fn finish_async(&mut self, session_key: &str, payload: &EndSessionPayload) {
+    while self.ig.flush_events(session_key) > 0 {}
+    if let Some(metadata) = self.pending_metadata.take() {
+      self.ig.update_metadata(&metadata, session_key, &mut self.metadata_snapshot);
+    }
+
+    match *self.ig.mode {
+      IndigaugeMode::Live => {
+        // Goes through the delivery worker behind the final batches and metadata instead of waiting for them.
+        self.ig.worker.submit(DeliveryJob::EndSession {
+          api_key: session_key.to_string(),
+          payload: serde_json::to_value(payload).unwrap_or_default(),
+        });
+      },
+      IndigaugeMode::Dev => {
+        if *self.ig.log_level <= IndigaugeLogLevel::Info {
+          info!(message = "DEVMODE: session ended", reason = payload.reason);
+        }
+      },
+      _ => {},
+    }
+  }
+
+  /// Blocking requests aren't possible on wasm, the requests are sent like when the session is ended manually.
+  #[cfg(target_family = "wasm")]
+  fn finish_blocking(&mut self, session_key: &str, payload: &EndSessionPayload) {
+    self.finish_async(session_key, payload);
+  }
+
+  #[cfg(not(target_family = "wasm"))]
+  fn finish_blocking(&mut self, session_key: &str, payload: &EndSessionPayload) {
+    let deadline = Instant::now() + self.ig.config.shutdown_timeout;
+    let mut unsent = Vec::new();
+    // Once a request fails, the rest is stored right away instead of waiting for more timeouts.
+    let mut online = true;
+
+    // Batches already handed to the delivery worker
+    while self.ig.worker.pending() > 0 {
+      let Ok(output) = self.ig.worker.recv_timeout(deadline.saturating_duration_since(Instant::now())) else {
+        break;
+      };
+
+      match output {
+        DeliveryOutput::EventBatch {
+          api_key,
+          batch,
+          body: Ok(body),
+        } => {
+          online = online
+            && self
+              .ig
+              .build_encoded_request(Method::POST, "events/batch", &api_key, body)
+              .is_ok_and(|request| self.ig.send_blocking(request, deadline));
+          if !online {
+            unsent.extend(batch.events);
+          }
+        },
+        output => self.ig.dispatch_delivery_output(output),
+      }
+    }
+
+    while !self.ig.buffered_events.is_empty() {
+      let events = self.ig.buffered_events.take_batch(&self.ig.config);
+      if online {
+        let batch = BatchEventPayload::new(events);
+        online = self
+          .ig
+          .encode_event_batch(&batch)
+          .ok()
+          .and_then(|body| self.ig.build_encoded_request(Method::POST, "events/batch", session_key, body).ok())
+          .is_some_and(|request| self.ig.send_blocking(request, deadline));
+        if !online {
+          unsent.extend(batch.events);
+        }
+      } else {
+        unsent.extend(events);
+      }
+    }
+
+    let pending_metadata = self.pending_metadata.take();
+    let patch = pending_metadata.and_then(|metadata| self.metadata_snapshot.patch(session_key, &metadata));
+    let metadata = patch.filter(|metadata| {
+      online = online
+        && encode_body(metadata, IndigaugeWireFormat::Json, IndigaugeCompression::None, usize::MAX)
+          .ok()
+          .and_then(|body| self.ig.build_encoded_request(Method::PATCH, "sessions", session_key, body).ok())
+          .is_some_and(|request| self.ig.send_blocking(request, deadline));
+      !online
+    });
+
+    online = online
+      && self
+        .ig
+        .build_post_request("sessions/end", session_key, payload)
+        .is_ok_and(|request| self.ig.send_blocking(request, deadline));
+
+    if online {
+      return;
+    }
+
+    let Some(local_session_id) = local_session_id() else {
+      return;
+    };
 
-  let reqwest_client = ig.build_post_request("sessions/end", &session_key, &json!({"reason": "ended"}));
+    let session = SpooledSession {
+      local_session_id: local_session_id.to_string(),
+      // Kept to register the session again if the token expires before the session is sent.
+      start: self.start_attempts.payload.clone(),
+      session_token: Some(session_key.to_string()),
+      ended_at: unix_timestamp_ms(),
+      end: serde_json::to_value(payload).ok(),
+      metadata,
+      events: unsent.iter().filter_map(|event| serde_json::to_value(event).ok()).collect(),
+    };
 
-  if let Ok(reqwest_client) = reqwest_client {
-    ig.reqwest_client.send(reqwest_client);
+    match spool::write(&self.ig.config, &session) {
+      Ok(()) => {
+        if *self.ig.log_level <= IndigaugeLogLevel::Warn {
+          warn!(message = "Shutdown flush timed out, stored the rest of the session on disk", events = unsent.len());
+        }
+      },
+      Err(error) => {
+        if *self.ig.log_level <= IndigaugeLogLevel::Error {
+          error!(message = "Failed to store the rest of the session", %error);
+        }
+      },
+    }
   }
 }
 
-pub(crate) fn handle_updated_metadata<M>(mut session_meta: ResMut<SessionMeta<M>>)
+/// Reads focus, minimization and input events to split the session into active and idle time, and ends the
+/// session if the player has been idle for longer than the idle timeout.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn track_activity(
+  mut commands: Commands,
+  mut activity: ResMut<IndigaugeActivity>,
+  mut timed_out: Local<bool>,
+  config: Res<IndigaugeConfig>,
+  time: Res<Time<Real>>,
+  mut focused: EventReader<WindowFocused>,
+  mut occluded: EventReader<WindowOccluded>,
+  mut keyboard: EventReader<KeyboardInput>,
+  mut mouse_buttons: EventReader<MouseButtonInput>,
+  mut mouse_motion: EventReader<MouseMotion>,
+  mut mouse_wheel: EventReader<MouseWheel>,
+  mut touches: EventReader<TouchInput>,
+  mut gamepad: EventReader<GamepadEvent>,
+) {
+  let now = time.elapsed();
+
+  if let Some(event) = focused.read().last() {
+    activity.set_focused(event.focused);
+  }
+  if let Some(event) = occluded.read().last() {
+    activity.set_minimized(event.occluded);
+  }
+
+  let has_input = keyboard.read().count()
+    + mouse_buttons.read().count()
+    + mouse_motion.read().count()
+    + mouse_wheel.read().count()
+    + touches.read().count()
+    + gamepad.read().count()
+    > 0;
+  if has_input {
+    activity.record_input(now);
+  }
+
+  match activity.update(now, time.delta(), config.idle_threshold) {
+    Some(ActivityChange::Idle(reason)) => {
+      crate::ig_info!("session.idle", { "reason": reason.as_str() });
+    },
+    Some(ActivityChange::Resumed(idle_for)) => {
+      crate::ig_info!("session.resumed", { "idleMs": idle_for.as_millis() as u64 });
+    },
+    None => {},
+  }
+
+  if let Some(idle_timeout) = config.idle_timeout
+    && !*timed_out
+    && activity.idle_for(now).is_some_and(|idle_for| idle_for >= idle_timeout)
+  {
+    *timed_out = true;
+    commands.trigger(EndSessionEvent::new(SessionEndReason::IdleTimeout));
+  }
+}
+
+/// Records the frame time of every frame for the next heartbeat.
+pub(crate) fn record_frame_time(mut frame_times: ResMut<FrameTimes>, time: Res<Time<Real>>) {
+  frame_times.record(time.delta());
+}
+
+/// Sends a heartbeat with the vitals of the game since the last heartbeat.
+pub(crate) fn send_heartbeat(
+  mut ig: BevyIndigauge,
+  session_key: Res<SessionApiKey>,
+  mut frame_times: ResMut<FrameTimes>,
+  context: Res<HeartbeatContext>,
+  activity: Option<Res<IndigaugeActivity>>,
+  mut process_memory: Local<ProcessMemory>,
+  remote_config: Option<Res<IndigaugeRemoteConfig>>,
+) {
+  if remote_config.is_some_and(|remote_config| !remote_config.is_collecting()) {
+    frame_times.take_stats();
+    return;
+  }
+
+  let frames = frame_times.take_stats();
+  let payload = HeartbeatPayload {
+    fps: frames.map(|(fps, _)| fps),
+    frame_time_ms: frames.map(|(_, percentiles)| percentiles),
+    memory_bytes: process_memory.resident_bytes(),
+    context: context.entries().clone(),
+    queue: Some(HeartbeatQueueStats::new(&ig.buffered_events, ig.worker.pending())),
+    ..HeartbeatPayload::new(activity.as_deref())
+  };
+
+  ig.send_heartbeat(&session_key, &payload);
+}
+
+/// Keeps the current value of the state `S` in the [`HeartbeatContext`] under the given key.
+///
+/// # Example
+/// ```ignore
+/// app.add_systems(Update, record_state_in_heartbeat::<GameState>("state"));
+/// ```
+pub fn record_state_in_heartbeat<S>(key: &'static str) -> impl FnMut(Option<Res<State<S>>>, ResMut<HeartbeatContext>)
 where
-  M: Resource + Serialize,
+  S: States,
 {
-  session_meta.is_changed = true;
+  move |state, mut context| match state {
+    Some(state) if state.is_changed() => context.set(key, format!("{:?}", state.get())),
+    None if context.get(key).is_some() => {
+      context.remove(key);
+    },
+    _ => {},
+  }
 }
 
-pub(crate) fn update_metadata<M>(
-  mut session_meta: ResMut<SessionMeta<M>>,
-  metadata: Res<M>,
+/// Serializes the metadata if it changed since it was last sent, so that it is sent with the final flush when the
+/// app exits.
+pub(crate) fn stage_final_metadata(
+  app_exit: EventReader<AppExit>,
+  window_close: EventReader<WindowCloseRequested>,
+  mut session_metadata: ResMut<SessionMetadata>,
+  mut pending_metadata: ResMut<PendingMetadata>,
+) {
+  if (app_exit.is_empty() && window_close.is_empty()) || !session_metadata.is_changed {
+    return;
+  }
+
+  session_metadata.is_changed = false;
+  **pending_metadata = session_metadata.document();
+}
+
+/// Stores a session that was never registered with the server on disk when the app exits, so that it can be
+/// registered on a later launch. Only used for offline-first sessions.
+#[cfg(not(target_family = "wasm"))]
+pub(crate) fn spool_unregistered_session<E>(
+  mut exit_events: EventReader<E>,
   mut ig: BevyIndigauge,
-  session_key: Res<SessionApiKey>,
+  mut attempts: ResMut<SessionStartAttempts>,
 ) where
-  M: Resource + Serialize,
+  E: Event,
+  for<'a> SessionEndReason: From<&'a E>,
+{
+  let Some(reason) = exit_events.read().last().map(SessionEndReason::from) else {
+    return;
+  };
+  if !ig.config.offline_first || *ig.mode != IndigaugeMode::Live {
+    return;
+  }
+
+  // Taking the payload makes sure the session is only stored once, even if several exit events are sent.
+  let (Some(local_session_id), Some(start)) = (local_session_id(), attempts.payload.take()) else {
+    return;
+  };
+
+  let session = SpooledSession {
+    local_session_id: local_session_id.to_string(),
+    start: Some(start),
+    session_token: None,
+    ended_at: unix_timestamp_ms(),
+    end: serde_json::to_value(EndSessionPayload::new(&reason, None)).ok(),
+    metadata: None,
+    events: ig
+      .buffered_events
+      .take_all()
+      .iter()
+      .filter_map(|event| serde_json::to_value(event).ok())
+      .collect(),
+  };
+
+  match spool::write(&ig.config, &session) {
+    Ok(()) => {
+      if *ig.log_level <= IndigaugeLogLevel::Info {
+        info!(message = "Stored unregistered session on disk", events = session.events.len());
+      }
+    },
+    Err(error) => {
+      if *ig.log_level <= IndigaugeLogLevel::Error {
+        error!(message = "Failed to store unregistered session", %error);
+      }
+    },
+  }
+}
+
+/// Registers sessions stored on disk by earlier launches, once the server is reachable. Continuation sessions
+/// don't register them again.
+#[cfg(not(target_family = "wasm"))]
+pub(crate) fn register_spooled_sessions(mut ig: BevyIndigauge, mut registered: Local<bool>) {
+  if *ig.mode != IndigaugeMode::Live || *registered {
+    return;
+  }
+  *registered = true;
+
+  for (path, session) in spool::read_all(&ig.config) {
+    ig.register_spooled_session(path, session);
+  }
+}
+
+/// Sends the next session start attempt once its backoff has elapsed.
+pub(crate) fn retry_session_start(
+  mut ig: BevyIndigauge,
+  mut commands: Commands,
+  mut attempts: ResMut<SessionStartAttempts>,
+  sys_info: Option<Res<SystemInfo>>,
+  render_info: Option<Res<RenderAdapterInfo>>,
+  player: Res<IndigaugePlayer>,
+) {
+  if attempts.retry_at.is_some_and(|retry_at| retry_at <= Instant::now()) {
+    attempts.retry_at = None;
+    commands.queue(SetSessionStatus(IndigaugeSessionStatus::Starting));
+    send_start_session_request(
+      &mut ig,
+      &mut commands,
+      &mut attempts,
+      sys_info.as_deref(),
+      render_info.as_deref(),
+      &player,
+    );
+  }
+}
+
+/// Serializes the metadata source `R` into the session metadata. Runs when the metadata is due and `R` changed since
+/// it was last serialized. Without a key, the fields of `R` are at the top level of the metadata.
+pub(crate) fn collect_session_meta<R>(
+  key: Option<String>,
+) -> impl FnMut(Res<R>, ResMut<SessionMetadata>, Res<IndigaugeLogLevel>)
+where
+  R: Resource + Serialize,
 {
-  if session_meta.is_changed {
-    session_meta.is_changed = false;
+  move |source, mut session_metadata, log_level| match serde_json::to_value(&*source) {
+    Ok(value) => session_metadata.set(key.as_deref(), value),
+    Err(error) => {
+      if *log_level <= IndigaugeLogLevel::Error {
+        error!(message = "Failed to serialize metadata", ?key, ?error);
+      }
+    },
+  }
+}
+
+/// Converts the reflected metadata source `R` into the `key` sub-object of the session metadata, like
+/// [`collect_session_meta`].
+pub(crate) fn collect_reflect_session_meta<R>(
+  key: String,
+) -> impl FnMut(Res<R>, ResMut<SessionMetadata>, IndigaugeReflect)
+where
+  R: Resource + Reflect,
+{
+  move |source, mut session_metadata, reflect| session_metadata.set(Some(&key), reflect.to_json(&*source))
+}
+
+/// Keeps the fields of the reflected resource `R` in the [`HeartbeatContext`], as `key.field` entries. Entries of
+/// fields that are gone, e.g. after an enum changed its variant, are removed.
+pub(crate) fn collect_reflect_heartbeat_context<R>(
+  key: String,
+) -> impl FnMut(Res<R>, ResMut<HeartbeatContext>, IndigaugeReflect)
+where
+  R: Resource + Reflect,
+{
+  move |source, mut context, reflect| {
+    let entries: Vec<_> = match reflect.to_json(&*source) {
+      serde_json::Value::Object(fields) => {
+        fields.into_iter().map(|(field, value)| (format!("{key}.{field}"), value)).collect()
+      },
+      value => vec![(key.clone(), value)],
+    };
+
+    let keys = entries.iter().map(|(key, _)| key.clone()).collect::<Vec<_>>();
+    context.remove_source(&key, &keys);
+
+    for (key, value) in entries {
+      match value {
+        serde_json::Value::String(value) => context.set(key, value),
+        value => context.set(key, value.to_string()),
+      }
+    }
+  }
+}
+
+/// Removes the entries of the reflected resource from the [`HeartbeatContext`] once it is removed.
+pub(crate) fn remove_reflect_heartbeat_context(key: String) -> impl FnMut(ResMut<HeartbeatContext>) {
+  move |mut context| context.remove_source(&key, &[])
+}
+
+/// Removes a metadata source from the session metadata once its resource is removed.
+pub(crate) fn remove_session_meta(key: Option<String>) -> impl FnMut(ResMut<SessionMetadata>) {
+  move |mut session_metadata| session_metadata.remove(key.as_deref())
+}
+
+/// Marks the metadata as due, so that the sources that changed are serialized and the metadata is sent.
+pub(crate) fn request_metadata_update(mut session_metadata: ResMut<SessionMetadata>) {
+  session_metadata.is_due = true;
+}
+
+/// Whether the metadata is sent this frame, see [`request_metadata_update`].
+pub(crate) fn metadata_due(session_metadata: Res<SessionMetadata>) -> bool {
+  session_metadata.is_due
+}
+
+/// Sends the metadata if it changed. After a failed update or once a new session was started, the full metadata is
+/// sent again.
+pub(crate) fn update_metadata(
+  mut session_metadata: ResMut<SessionMetadata>,
+  mut snapshot: ResMut<MetadataSnapshot>,
+  mut ig: BevyIndigauge,
+  session_key: Option<Res<SessionApiKey>>,
+) {
+  session_metadata.is_due = false;
+  let Some(session_key) = session_key else {
+    return;
+  };
+
+  if session_metadata.is_changed || snapshot.resync || session_key.is_changed() {
+    session_metadata.is_changed = false;
+    snapshot.resync = false;
+
+    if let Some(metadata) = session_metadata.document() {
+      ig.update_metadata(&metadata, &session_key, &mut snapshot);
+    }
+  }
+}
+
+#[cfg(all(test, not(target_family = "wasm")))]
+mod tests {
+  use bevy::ecs::system::RunSystemOnce;
+  use bevy_mod_reqwest::ReqwestClient;
+
+  use super::*;
+  use crate::{
+    api_types::EventPayload,
+    event::resources::{BufferedEvents, QueuedEvent},
+    session::init_local_session_id,
+    transport::TransportState,
+    worker::DeliveryWorker,
+  };
+
+  #[derive(Resource, Serialize)]
+  struct Loadout {
+    weapon: &'static str,
+  }
+
+  #[test]
+  fn metadata_sources_are_serialized_once_the_metadata_is_due() {
+    let mut app = App::new();
+    app
+      .add_session_meta::<Loadout>("loadout")
+      .insert_resource(Loadout { weapon: "sword" })
+      .insert_resource(IndigaugeLogLevel::Error);
+
+    app.update();
+    assert_eq!(app.world().resource::<SessionMetadata>().document(), None);
+
+    app.world_mut().resource_mut::<Loadout>().weapon = "bow";
+    app.world_mut().resource_mut::<SessionMetadata>().is_due = true;
+    app.update();
+    let document = app.world().resource::<SessionMetadata>().document();
+    assert_eq!(document, Some(serde_json::json!({ "loadout": { "weapon": "bow" } })));
+  }
+
+  #[derive(Resource, Reflect)]
+  enum Stance {
+    Standing,
+    Crouched { speed: u32 },
+  }
+
+  #[test]
+  fn reflected_heartbeat_context_drops_entries_that_are_gone() {
+    let mut app = App::new();
+    app
+      .add_reflect_heartbeat_context::<Stance>("stance")
+      .insert_resource(Stance::Crouched { speed: 2 });
+    app.world_mut().resource_mut::<HeartbeatContext>().set("level", "forest-2");
+
+    app.update();
+    let context = app.world().resource::<HeartbeatContext>();
+    assert_eq!(context.get("stance.Crouched"), Some(r#"{"speed":2}"#));
+
+    *app.world_mut().resource_mut::<Stance>() = Stance::Standing;
+    app.update();
+    let context = app.world().resource::<HeartbeatContext>();
+    assert_eq!(context.get("stance"), Some("Standing"));
+    assert_eq!(context.get("stance.Crouched"), None);
+
+    app.world_mut().remove_resource::<Stance>();
+    app.update();
+    let context = app.world().resource::<HeartbeatContext>();
+    assert_eq!(context.entries().keys().collect::<Vec<_>>(), vec!["level"]);
+  }
+
+  #[test]
+  fn sessions_are_stored_when_the_server_is_unreachable_on_exit() {
+    let storage_dir = std::env::temp_dir().join(format!("indigauge-finish-{}", std::process::id()));
+    let mut config = IndigaugeConfig::new("Game", "public-key", "1.0.0");
+    // Nothing listens on port 1, so every request fails right away.
+    config.api_base = "http://127.0.0.1:1".to_string();
+    config.storage_dir = Some(storage_dir.clone());
+
+    let mut event = QueuedEvent::new(
+      EventPayload {
+        event_type: "game.start".into(),
+        metadata: None,
+        level: "info",
+        elapsed_ms: 0,
+        timestamp: 0,
+        seq: 0,
+        idempotency_key: None,
+        context: None,
+        game_time: None,
+      },
+      0,
+    );
+    event.measure();
+    let mut buffered_events = BufferedEvents::default();
+    buffered_events.push(event, &config);
+
+    let mut world = World::new();
+    world.insert_resource(config.clone());
+    world.insert_resource(buffered_events);
+    world.insert_resource(IndigaugeMode::Live);
+    world.insert_resource(IndigaugeLogLevel::Error);
+    world.insert_resource(DeliveryWorker::spawn(&IndigaugeLogLevel::Info));
+    world.init_resource::<ReqwestClient>();
+    world.init_resource::<TransportState>();
+    world.init_resource::<EventSinks>();
+    world.init_resource::<PendingMetadata>();
+    world.init_resource::<MetadataSnapshot>();
+    world.init_resource::<Events<IndigaugeSessionStatusChanged>>();
+    world.insert_resource(SessionStartAttempts {
+      payload: Some(serde_json::json!({ "clientVersion": "1.0.0" })),
+      ..Default::default()
+    });
+    init_local_session_id();
+
+    world
+      .run_system_once(|mut finisher: SessionFinisher| {
+        finisher.finish("session-token", &SessionEndReason::Quit, None, true);
+      })
+      .unwrap();
+
+    let spooled = spool::read_all(&config);
+    let _ = std::fs::remove_dir_all(&storage_dir);
 
-    ig.update_metadata(&*metadata, &session_key);
+    assert_eq!(spooled.len(), 1);
+    let session = &spooled[0].1;
+    assert_eq!(session.session_token.as_deref(), Some("session-token"));
+    assert_eq!(session.start, Some(serde_json::json!({ "clientVersion": "1.0.0" })));
+    assert_eq!(session.events.len(), 1);
+    assert_eq!(session.end.as_ref().and_then(|end| end.get("reason")), Some(&serde_json::json!("quit")));
   }
 }
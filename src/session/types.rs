@@ -0,0 +1,105 @@
+use bevy::{app::AppExit, window::WindowCloseRequested};
+
+/// Why a session ended. Sent to the server when the session is ended.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SessionEndReason {
+  /// The game ended the session, e.g. with the [`end_session`](crate::prelude::end_session) system.
+  Ended,
+  /// The player quit the game, e.g. from the main menu. Also used for `AppExit::Success`.
+  Quit,
+  /// The window was closed.
+  WindowClosed,
+  /// The app exited with `AppExit::Error`. Contains the exit code.
+  Error(u8),
+  /// The game panicked.
+  Crash,
+  /// The player was idle for too long.
+  IdleTimeout,
+  /// A reason defined by the game. Sent as `custom`, with the reason alongside, so that it can't be mistaken for one
+  /// of the reasons above.
+  Custom(String),
+}
+
+impl SessionEndReason {
+  /// The reason as sent to the server.
+  pub fn as_str(&self) -> &str {
+    match self {
+      SessionEndReason::Ended => "ended",
+      SessionEndReason::Quit => "quit",
+      SessionEndReason::WindowClosed => "window_closed",
+      SessionEndReason::Error(_) => "error",
+      SessionEndReason::Crash => "crashed",
+      SessionEndReason::IdleTimeout => "idle_timeout",
+      SessionEndReason::Custom(_) => "custom",
+    }
+  }
+
+  /// The reason defined by the game, if it is a custom reason.
+  pub fn custom_reason(&self) -> Option<&str> {
+    match self {
+      SessionEndReason::Custom(reason) => Some(reason),
+      _ => None,
+    }
+  }
+
+  /// The exit code, if the app exited with an error.
+  pub fn exit_code(&self) -> Option<u8> {
+    match self {
+      SessionEndReason::Error(code) => Some(*code),
+      _ => None,
+    }
+  }
+}
+
+impl From<&AppExit> for SessionEndReason {
+  fn from(exit: &AppExit) -> Self {
+    match exit {
+      AppExit::Success => SessionEndReason::Quit,
+      AppExit::Error(code) => SessionEndReason::Error(code.get()),
+    }
+  }
+}
+
+impl From<&WindowCloseRequested> for SessionEndReason {
+  fn from(_: &WindowCloseRequested) -> Self {
+    SessionEndReason::WindowClosed
+  }
+}
+
+/// Why the player is considered idle.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IdleReason {
+  /// The window lost focus.
+  Unfocused,
+  /// The window was minimized or is fully hidden.
+  Minimized,
+  /// There was no input for longer than the idle threshold.
+  Inactive,
+}
+
+impl IdleReason {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      IdleReason::Unfocused => "unfocused",
+      IdleReason::Minimized => "minimized",
+      IdleReason::Inactive => "inactive",
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn exit_events_map_to_end_reasons() {
+    assert_eq!(SessionEndReason::from(&AppExit::Success), SessionEndReason::Quit);
+    assert_eq!(SessionEndReason::from(&AppExit::from_code(3)), SessionEndReason::Error(3));
+    assert_eq!(SessionEndReason::Error(3).exit_code(), Some(3));
+
+    let custom = SessionEndReason::Custom("quit".to_string());
+    assert_eq!(custom.as_str(), "custom");
+    assert_eq!(custom.custom_reason(), Some("quit"));
+    assert_eq!(SessionEndReason::Quit.custom_reason(), None);
+  }
+}
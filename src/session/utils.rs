@@ -152,14 +152,133 @@ fn extract_ryzen_gen(name: &str) -> Option<String> {
   None
 }
 
+/// Generates a random id for a session started on the client.
+#[cfg(not(target_family = "wasm"))]
+pub(crate) fn generate_session_id() -> String {
+  uuid::Uuid::new_v4().to_string()
+}
+
+/// Generates a random id for a session started on the client.
+#[cfg(target_family = "wasm")]
+pub(crate) fn generate_session_id() -> String {
+  use std::sync::atomic::{AtomicU64, Ordering};
+
+  static FALLBACK_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+  let mut bytes = [0u8; 16];
+  if getrandom::fill(&mut bytes).is_err() {
+    // Not random, but still distinct between page loads and between ids generated on the same page.
+    let page_time = web_sys::window()
+      .and_then(|window| window.performance())
+      .map(|performance| performance.now().to_bits())
+      .unwrap_or_default();
+    let counter = FALLBACK_COUNTER.fetch_add(1, Ordering::Relaxed);
+    bytes[..8].copy_from_slice(&crate::utils::unix_timestamp_ms().to_le_bytes());
+    bytes[8..].copy_from_slice(&(page_time ^ counter).to_le_bytes());
+  }
+  bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// Computes the JSON merge patch (RFC 7386) that turns `previous` into `current`, or `None` if they are equal.
+/// Removed keys are set to `null`.
+pub(crate) fn json_merge_patch(previous: &serde_json::Value, current: &serde_json::Value) -> Option<serde_json::Value> {
+  use serde_json::{Map, Value};
+
+  let (Value::Object(previous), Value::Object(current)) = (previous, current) else {
+    return (previous != current).then(|| current.clone());
+  };
+
+  let removed = previous
+    .keys()
+    .filter(|key| !current.contains_key(*key))
+    .map(|key| (key.clone(), Value::Null));
+  let changed = current.iter().filter_map(|(key, value)| match previous.get(key) {
+    Some(previous) => json_merge_patch(previous, value).map(|patch| (key.clone(), patch)),
+    None => Some((key.clone(), value.clone())),
+  });
+
+  let patch = removed.chain(changed).collect::<Map<_, _>>();
+  (!patch.is_empty()).then_some(Value::Object(patch))
+}
+
+/// Computes the JSON merge patch that turns any document with at most the keys of `sent` into `current`: every value
+/// of `current`, and `null` for every key of `sent` that `current` doesn't have.
+pub(crate) fn json_replace_patch(sent: &serde_json::Value, current: &serde_json::Value) -> serde_json::Value {
+  use serde_json::{Map, Value};
+
+  let (Value::Object(sent), Value::Object(current)) = (sent, current) else {
+    return current.clone();
+  };
+
+  let removed = sent
+    .keys()
+    .filter(|key| !current.contains_key(*key))
+    .map(|key| (key.clone(), Value::Null));
+  let replaced = current.iter().map(|(key, value)| match sent.get(key) {
+    Some(sent) => (key.clone(), json_replace_patch(sent, value)),
+    None => (key.clone(), value.clone()),
+  });
+
+  Value::Object(removed.chain(replaced).collect::<Map<_, _>>())
+}
+
+/// Adds the keys of `document` to `keys`, recursing into objects. Only the keys of `keys` are meaningful, it tracks
+/// every key that was set by a merge patch.
+pub(crate) fn json_merge_keys(keys: &mut serde_json::Value, document: &serde_json::Value) {
+  use serde_json::Value;
+
+  match (keys, document) {
+    (Value::Object(keys), Value::Object(document)) => {
+      for (key, value) in document {
+        match keys.get_mut(key) {
+          Some(existing) => json_merge_keys(existing, value),
+          None => {
+            keys.insert(key.clone(), value.clone());
+          },
+        }
+      }
+    },
+    (keys, Value::Object(_)) => *keys = document.clone(),
+    _ => {},
+  }
+}
+
+/// Reads the memory usage of the game process.
+#[derive(Default)]
+pub(crate) struct ProcessMemory {
+  #[cfg(not(target_family = "wasm"))]
+  system: sysinfo::System,
+}
+
+impl ProcessMemory {
+  /// Resident memory of the game process in bytes, or `None` if it can't be read on this platform.
+  #[cfg(not(target_family = "wasm"))]
+  pub(crate) fn resident_bytes(&mut self) -> Option<u64> {
+    use sysinfo::{ProcessRefreshKind, ProcessesToUpdate};
+
+    let pid = sysinfo::get_current_pid().ok()?;
+    self
+      .system
+      .refresh_processes_specifics(ProcessesToUpdate::Some(&[pid]), false, ProcessRefreshKind::new().with_memory());
+    self.system.process(pid).map(sysinfo::Process::memory)
+  }
+
+  #[cfg(target_family = "wasm")]
+  pub(crate) fn resident_bytes(&mut self) -> Option<u64> {
+    None
+  }
+}
+
 #[cfg(all(feature = "panic_handler", not(target_family = "wasm")))]
 pub fn panic_handler(
   host_origin: String,
   session_api_key: String,
 ) -> impl Fn(&std::panic::PanicHookInfo) + Send + Sync + 'static {
   use crate::{
-    api_types::{EventPayload, EventPayloadCtx},
-    session::SESSION_START_INSTANT,
+    api_types::{EndSessionPayload, EventPayload, EventPayloadCtx},
+    event::metadata::EventMetadata,
+    session::{SESSION_START_INSTANT, next_event_seq, types::SessionEndReason},
+    utils::unix_timestamp_ms,
   };
   use serde_json::json;
   use std::time::Instant;
@@ -176,20 +295,23 @@ pub fn panic_handler(
       let metadata = info
         .payload()
         .downcast_ref::<&str>()
-        .map(|s| json!({"message": s.to_string()}));
+        .map(|s| EventMetadata::Json(json!({"message": s.to_string()})));
 
       let context = info.location().map(|loc| EventPayloadCtx {
-        file: loc.file().to_string(),
+        file: loc.file().to_string().into(),
         line: loc.line(),
         module: None,
       });
       let payload = EventPayload {
         level: "fatal",
-        event_type: "game.crash".to_string(),
+        event_type: "game.crash".into(),
         elapsed_ms,
+        timestamp: unix_timestamp_ms(),
+        seq: next_event_seq().1,
         metadata,
         idempotency_key: None,
         context,
+        game_time: None,
       };
 
       let single_event_endpoint = format!("{}/v1/events", host_origin);
@@ -204,8 +326,26 @@ pub fn panic_handler(
       let _ = client
         .post(&end_session_endpoint)
         .header("X-Indigauge-Key", &session_api_key)
-        .json(&json!({"reason": "crashed"}))
+        .json(&EndSessionPayload::new(&SessionEndReason::Crash, None))
         .send();
     }
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serde_json::json;
+
+  #[test]
+  fn merge_patch_contains_only_changed_keys() {
+    let previous = json!({ "level": 1, "gold": 10, "build": { "class": "mage", "perks": ["a"] }, "party": "x" });
+    let current = json!({ "level": 2, "gold": 10, "build": { "class": "mage", "perks": ["a", "b"] } });
+
+    assert_eq!(
+      json_merge_patch(&previous, &current),
+      Some(json!({ "level": 2, "build": { "perks": ["a", "b"] }, "party": null }))
+    );
+    assert_eq!(json_merge_patch(&current, &current), None);
+  }
+}
@@ -0,0 +1,165 @@
+use std::{
+  cmp::Reverse,
+  fs,
+  path::{Path, PathBuf},
+  time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{config::IndigaugeConfig, utils::unix_timestamp_ms};
+
+/// Stored sessions that ended longer ago than this are deleted instead of sent.
+const MAX_AGE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+/// At most this many stored sessions are kept. The sessions that ended first are deleted.
+const MAX_SESSIONS: usize = 32;
+
+/// Data of a session that could not be sent before the game exited, with everything needed to send it on a later
+/// launch. Either the session was never registered with the server, or the final flush ran out of time.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct SpooledSession {
+  pub local_session_id: String,
+  /// Body of the `sessions/start` request. Used to register the session if it never was, or to register it again if
+  /// its token was rejected.
+  #[serde(default)]
+  pub start: Option<serde_json::Value>,
+  /// Token of the registered session.
+  #[serde(default)]
+  pub session_token: Option<String>,
+  /// UTC unix timestamp in milliseconds of when the session ended.
+  pub ended_at: u64,
+  /// Body of the `sessions/end` request, if it wasn't sent.
+  #[serde(default)]
+  pub end: Option<serde_json::Value>,
+  /// Metadata that wasn't sent.
+  #[serde(default)]
+  pub metadata: Option<serde_json::Value>,
+  pub events: Vec<serde_json::Value>,
+}
+
+fn spool_dir(config: &IndigaugeConfig) -> Option<PathBuf> {
+  config.storage_dir().map(|dir| dir.join("sessions"))
+}
+
+/// Writes the session to the spool directory of the game.
+pub(crate) fn write(config: &IndigaugeConfig, session: &SpooledSession) -> std::io::Result<()> {
+  write_in(&spool_dir(config).ok_or(std::io::ErrorKind::NotFound)?, session)
+}
+
+/// Reads every spooled session of the game. Files that can't be read are skipped. Sessions beyond the age and count
+/// limits are deleted.
+pub(crate) fn read_all(config: &IndigaugeConfig) -> Vec<(PathBuf, SpooledSession)> {
+  spool_dir(config).map(|dir| read_all_in(&dir, unix_timestamp_ms())).unwrap_or_default()
+}
+
+fn write_in(dir: &Path, session: &SpooledSession) -> std::io::Result<()> {
+  fs::create_dir_all(dir)?;
+
+  let body = serde_json::to_vec(session)?;
+  fs::write(dir.join(format!("{}.json", session.local_session_id)), body)
+}
+
+fn read_all_in(dir: &Path, now: u64) -> Vec<(PathBuf, SpooledSession)> {
+  let Ok(entries) = fs::read_dir(dir) else {
+    return Vec::new();
+  };
+
+  let mut sessions = entries
+    .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+    .filter(|path| path.extension().is_some_and(|extension| extension == "json"))
+    .filter_map(|path| {
+      let session = fs::read(&path).ok().and_then(|body| serde_json::from_slice::<SpooledSession>(&body).ok())?;
+      Some((path, session))
+    })
+    .collect::<Vec<_>>();
+
+  sessions.sort_by_key(|(_, session)| Reverse(session.ended_at));
+  let max_age = MAX_AGE.as_millis() as u64;
+  let (kept, pruned): (Vec<_>, Vec<_>) = sessions
+    .into_iter()
+    .enumerate()
+    .partition(|(index, (_, session))| *index < MAX_SESSIONS && now.saturating_sub(session.ended_at) <= max_age);
+
+  for (_, (path, _)) in pruned {
+    remove(&path);
+  }
+  kept.into_iter().map(|(_, spooled)| spooled).collect()
+}
+
+/// Removes a spooled session once it has been registered and its events were sent, or once the server rejected it.
+pub(crate) fn remove(path: &Path) {
+  let _ = fs::remove_file(path);
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serde_json::json;
+
+  fn spooled_session() -> SpooledSession {
+    SpooledSession {
+      local_session_id: "local-session".to_string(),
+      start: Some(json!({ "clientVersion": "1.0.0", "localSessionId": "local-session" })),
+      session_token: None,
+      ended_at: 1_700_000_000_000,
+      end: Some(json!({ "reason": "window_closed", "exitCode": null, "metadata": null })),
+      metadata: None,
+      events: vec![json!({ "eventType": "game.start", "level": "info" })],
+    }
+  }
+
+  #[test]
+  fn spooled_sessions_round_trip_through_json() {
+    let session = spooled_session();
+    let body = serde_json::to_vec(&session).unwrap();
+
+    assert_eq!(serde_json::from_slice::<SpooledSession>(&body).unwrap(), session);
+  }
+
+  #[test]
+  fn spooled_sessions_are_written_read_and_removed() {
+    let dir = std::env::temp_dir().join(format!("indigauge-spool-{}", std::process::id()));
+    write_in(&dir, &spooled_session()).unwrap();
+    fs::write(dir.join("notes.txt"), "not a session").unwrap();
+
+    let now = spooled_session().ended_at;
+    let spooled = read_all_in(&dir, now);
+    assert_eq!(spooled.len(), 1);
+    assert_eq!(spooled[0].0, dir.join("local-session.json"));
+    assert_eq!(spooled[0].1, spooled_session());
+
+    remove(&spooled[0].0);
+    assert!(read_all_in(&dir, now).is_empty());
+
+    let _ = fs::remove_dir_all(dir);
+  }
+
+  #[test]
+  fn old_and_excess_spooled_sessions_are_deleted() {
+    let dir = std::env::temp_dir().join(format!("indigauge-spool-pruned-{}", std::process::id()));
+    let now = spooled_session().ended_at;
+    let hour = 60 * 60 * 1000;
+    for index in 0..MAX_SESSIONS as u64 + 2 {
+      let session = SpooledSession {
+        local_session_id: format!("session-{index}"),
+        ended_at: now - index * hour,
+        ..spooled_session()
+      };
+      write_in(&dir, &session).unwrap();
+    }
+    let expired = SpooledSession {
+      local_session_id: "expired".to_string(),
+      ended_at: now - MAX_AGE.as_millis() as u64 - 1,
+      ..spooled_session()
+    };
+    write_in(&dir, &expired).unwrap();
+
+    let spooled = read_all_in(&dir, now);
+    assert_eq!(spooled.len(), MAX_SESSIONS);
+    assert!(spooled.iter().all(|(_, session)| session.ended_at > now - MAX_SESSIONS as u64 * hour));
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), MAX_SESSIONS);
+
+    let _ = fs::remove_dir_all(dir);
+  }
+}
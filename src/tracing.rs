@@ -210,6 +210,7 @@ impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for IndigaugeLayer {
 mod tests {
   use super::*;
   use crate::api_types::{EventPayload, EventPayloadCtx};
+  use crate::event::metadata::EventMetadata;
 
   use serde_json::json;
   use std::sync::{Arc, Mutex};
@@ -244,17 +245,20 @@ mod tests {
     ) {
       let module = if module.is_empty() { None } else { Some(module) };
       let context = matches!(level, "warn" | "error").then(|| EventPayloadCtx {
-        file: file.to_string(),
+        file: file.into(),
         line,
         module,
       });
       let payload = EventPayload {
         level,
-        event_type: event_type.to_string(),
+        event_type: event_type.to_string().into(),
         elapsed_ms: 1,
-        metadata,
+        timestamp: 1,
+        seq: 0,
+        metadata: metadata.map(EventMetadata::Json),
         idempotency_key: None,
         context,
+        game_time: None,
       };
       self.events.lock().unwrap().push(payload);
     }
@@ -309,7 +313,7 @@ mod tests {
             event.metadata,
             Some(json!({
               "message": message
-            }))
+            }).into())
           );
         }
 
@@ -368,7 +372,7 @@ mod tests {
       events[0].metadata,
       Some(json!({
         "message": "Test default error event type"
-      }))
+      }).into())
     );
   }
 
@@ -398,7 +402,7 @@ mod tests {
       events[0].metadata,
       Some(json!({
         "message": "Test set ig"
-      }))
+      }).into())
     );
   }
 
@@ -422,15 +426,18 @@ mod tests {
       events[0],
       EventPayload {
         level: "info",
-        event_type: "tracing.info".to_string(),
+        event_type: "tracing.info".into(),
         elapsed_ms: 1,
+        timestamp: 1,
+        seq: 0,
         metadata: Some(json!({
           "foo": 42,
           "bar": "baz",
           "message": "checking fields"
-        })),
+        }).into()),
         idempotency_key: None,
         context: None,
+        game_time: None,
       }
     );
   }
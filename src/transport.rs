@@ -0,0 +1,306 @@
+use std::time::{Duration, Instant};
+
+use bevy::prelude::*;
+use serde::Serialize;
+
+/// Compression applied to request bodies that are larger than the configured threshold.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum IndigaugeCompression {
+  /// Send request bodies uncompressed.
+  #[default]
+  None,
+  /// Compress request bodies with gzip. Requires the `gzip` feature.
+  #[cfg(feature = "gzip")]
+  Gzip,
+  /// Compress request bodies with zstd. Requires the `zstd` feature.
+  #[cfg(feature = "zstd")]
+  Zstd,
+}
+
+impl IndigaugeCompression {
+  /// The `Content-Encoding` header value for this compression.
+  pub fn content_encoding(&self) -> Option<&'static str> {
+    match self {
+      IndigaugeCompression::None => None,
+      #[cfg(feature = "gzip")]
+      IndigaugeCompression::Gzip => Some("gzip"),
+      #[cfg(feature = "zstd")]
+      IndigaugeCompression::Zstd => Some("zstd"),
+    }
+  }
+
+  pub(crate) fn compress(&self, bytes: &[u8]) -> std::io::Result<Vec<u8>> {
+    match self {
+      IndigaugeCompression::None => Ok(bytes.to_vec()),
+      #[cfg(feature = "gzip")]
+      IndigaugeCompression::Gzip => {
+        use flate2::{Compression, write::GzEncoder};
+        use std::io::Write;
+
+        let mut encoder = GzEncoder::new(Vec::with_capacity(bytes.len() / 4), Compression::default());
+        encoder.write_all(bytes)?;
+        encoder.finish()
+      },
+      #[cfg(feature = "zstd")]
+      IndigaugeCompression::Zstd => zstd::encode_all(bytes, 0),
+    }
+  }
+}
+
+/// Serialization format used for event batch request bodies.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum IndigaugeWireFormat {
+  /// Send request bodies as JSON.
+  #[default]
+  Json,
+  /// Send request bodies as MessagePack, with the same schema as the JSON body. Requires the `msgpack` feature.
+  #[cfg(feature = "msgpack")]
+  MessagePack,
+  /// Send request bodies as CBOR, with the same schema as the JSON body. Requires the `cbor` feature.
+  #[cfg(feature = "cbor")]
+  Cbor,
+}
+
+impl IndigaugeWireFormat {
+  /// The `Content-Type` header value for this format.
+  pub fn content_type(&self) -> &'static str {
+    match self {
+      IndigaugeWireFormat::Json => "application/json",
+      #[cfg(feature = "msgpack")]
+      IndigaugeWireFormat::MessagePack => "application/msgpack",
+      #[cfg(feature = "cbor")]
+      IndigaugeWireFormat::Cbor => "application/cbor",
+    }
+  }
+
+  pub(crate) fn serialize<S>(&self, payload: &S) -> Result<Vec<u8>, TransportError>
+  where
+    S: Serialize,
+  {
+    match self {
+      IndigaugeWireFormat::Json => serde_json::to_vec(payload).map_err(TransportError::Serialize),
+      #[cfg(feature = "msgpack")]
+      IndigaugeWireFormat::MessagePack => rmp_serde::to_vec_named(payload).map_err(TransportError::MessagePack),
+      #[cfg(feature = "cbor")]
+      IndigaugeWireFormat::Cbor => {
+        let mut bytes = Vec::new();
+        ciborium::into_writer(payload, &mut bytes).map_err(TransportError::Cbor)?;
+        Ok(bytes)
+      },
+    }
+  }
+}
+
+/// Transport state shared between requests.
+#[derive(Resource, Default)]
+pub struct TransportState {
+  /// Set when the server rejected a compressed request. Later requests are sent uncompressed.
+  pub(crate) compression_rejected: bool,
+  /// Set when the server rejected a binary request. Later requests are sent as JSON.
+  pub(crate) wire_format_rejected: bool,
+  /// Set when the server responded with `429 Too Many Requests`. Event batches are held back until then.
+  pub(crate) rate_limited_until: Option<Instant>,
+}
+
+impl TransportState {
+  /// Holds event batches back for the number of seconds in the `Retry-After` header of the response, or for
+  /// `fallback` if the header is missing or not a number of seconds.
+  pub(crate) fn rate_limit(&mut self, retry_after: Option<&str>, fallback: Duration) {
+    let wait = retry_after
+      .and_then(|retry_after| retry_after.trim().parse().ok())
+      .map(Duration::from_secs)
+      .unwrap_or(fallback);
+    self.rate_limited_until = Some(Instant::now() + wait);
+  }
+
+  /// Whether the server asked to slow down and the wait isn't over yet.
+  pub(crate) fn is_rate_limited(&self) -> bool {
+    self.rate_limited_until.is_some_and(|until| Instant::now() < until)
+  }
+}
+
+#[derive(Debug)]
+pub enum TransportError {
+  Serialize(serde_json::Error),
+  #[cfg(feature = "msgpack")]
+  MessagePack(rmp_serde::encode::Error),
+  #[cfg(feature = "cbor")]
+  Cbor(ciborium::ser::Error<std::io::Error>),
+}
+
+impl std::fmt::Display for TransportError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      TransportError::Serialize(error) => write!(f, "failed to serialize request body: {error}"),
+      #[cfg(feature = "msgpack")]
+      TransportError::MessagePack(error) => write!(f, "failed to serialize request body as MessagePack: {error}"),
+      #[cfg(feature = "cbor")]
+      TransportError::Cbor(error) => write!(f, "failed to serialize request body as CBOR: {error}"),
+    }
+  }
+}
+
+/// How a request body was encoded.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct BodyEncoding {
+  pub wire_format: IndigaugeWireFormat,
+  pub compressed: bool,
+}
+
+pub(crate) struct EncodedBody {
+  pub bytes: Vec<u8>,
+  pub content_type: &'static str,
+  pub content_encoding: Option<&'static str>,
+  pub encoding: BodyEncoding,
+}
+
+/// Serializes the payload in the given format and compresses it if it is at least `threshold` bytes.
+/// Falls back to the uncompressed body if compression fails.
+pub(crate) fn encode_body<S>(
+  payload: &S,
+  wire_format: IndigaugeWireFormat,
+  compression: IndigaugeCompression,
+  threshold: usize,
+) -> Result<EncodedBody, TransportError>
+where
+  S: Serialize,
+{
+  let bytes = wire_format.serialize(payload)?;
+
+  if let Some(content_encoding) = compression.content_encoding()
+    && bytes.len() >= threshold
+    && let Ok(compressed) = compression.compress(&bytes)
+  {
+    return Ok(EncodedBody {
+      bytes: compressed,
+      content_type: wire_format.content_type(),
+      content_encoding: Some(content_encoding),
+      encoding: BodyEncoding {
+        wire_format,
+        compressed: true,
+      },
+    });
+  }
+
+  Ok(EncodedBody {
+    bytes,
+    content_type: wire_format.content_type(),
+    content_encoding: None,
+    encoding: BodyEncoding {
+      wire_format,
+      compressed: false,
+    },
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serde_json::json;
+
+  #[test]
+  fn uncompressed_bodies_are_plain_json() {
+    let payload = json!({ "events": [] });
+    let body = encode_body(&payload, IndigaugeWireFormat::Json, IndigaugeCompression::None, 0).expect("Encoded body");
+
+    assert_eq!(body.content_encoding, None);
+    assert_eq!(body.bytes, serde_json::to_vec(&payload).unwrap());
+  }
+
+  #[test]
+  fn rate_limits_honor_retry_after_seconds() {
+    let mut transport = TransportState::default();
+    assert!(!transport.is_rate_limited());
+
+    transport.rate_limit(Some("120"), Duration::ZERO);
+    assert!(transport.is_rate_limited());
+
+    // HTTP dates are not parsed, the fallback applies.
+    transport.rate_limit(Some("Wed, 21 Oct 2015 07:28:00 GMT"), Duration::ZERO);
+    assert!(!transport.is_rate_limited());
+  }
+
+  #[cfg(feature = "gzip")]
+  #[test]
+  fn gzip_bodies_round_trip() {
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    let payload = json!({ "events": vec![json!({ "eventType": "ui.click", "metadata": { "button": "play" } }); 64] });
+
+    let small = encode_body(&payload, IndigaugeWireFormat::Json, IndigaugeCompression::Gzip, usize::MAX)
+      .expect("Encoded body");
+    assert_eq!(small.content_encoding, None);
+
+    let body = encode_body(&payload, IndigaugeWireFormat::Json, IndigaugeCompression::Gzip, 0).expect("Encoded body");
+    assert_eq!(body.content_encoding, Some("gzip"));
+    assert!(body.bytes.len() < small.bytes.len());
+
+    let mut decoded = Vec::new();
+    GzDecoder::new(body.bytes.as_slice())
+      .read_to_end(&mut decoded)
+      .expect("Decoded body");
+    assert_eq!(decoded, small.bytes);
+  }
+
+  #[cfg(any(feature = "msgpack", feature = "cbor"))]
+  fn sample_batch() -> crate::api_types::BatchEventPayload {
+    use crate::api_types::{BatchEventPayload, EventGameTime, EventPayload, EventPayloadCtx};
+
+    BatchEventPayload {
+      events: vec![
+        EventPayload {
+          event_type: "ui.click".into(),
+          metadata: Some(json!({ "button": "play", "x": 128, "y": 256.5, "nested": { "list": [1, 2, 3] } }).into()),
+          level: "info",
+          elapsed_ms: 1234,
+          timestamp: 1_700_000_000_000,
+          seq: 7,
+          idempotency_key: None,
+          context: None,
+          game_time: Some(EventGameTime {
+            frame: 42,
+            virtual_elapsed_ms: 1200,
+            fixed_tick: 75,
+          }),
+        },
+        EventPayload {
+          event_type: "game.error".into(),
+          metadata: None,
+          level: "error",
+          elapsed_ms: u64::MAX as u128 + 1,
+          timestamp: 1_700_000_000_001,
+          seq: 8,
+          idempotency_key: Some("key".to_string()),
+          context: Some(EventPayloadCtx {
+            file: "src/main.rs".into(),
+            line: 12,
+            module: Some("game"),
+          }),
+          game_time: None,
+        },
+      ],
+      local_session_id: Some("local-session"),
+    }
+  }
+
+  #[cfg(feature = "msgpack")]
+  #[test]
+  fn message_pack_batches_match_json_schema() {
+    let batch = sample_batch();
+    let bytes = IndigaugeWireFormat::MessagePack.serialize(&batch).expect("MessagePack body");
+    let decoded: serde_json::Value = rmp_serde::from_slice(&bytes).expect("Decoded body");
+
+    assert_eq!(decoded, serde_json::to_value(&batch).unwrap());
+  }
+
+  #[cfg(feature = "cbor")]
+  #[test]
+  fn cbor_batches_match_json_schema() {
+    let batch = sample_batch();
+    let bytes = IndigaugeWireFormat::Cbor.serialize(&batch).expect("CBOR body");
+    let decoded: serde_json::Value = ciborium::from_reader(bytes.as_slice()).expect("Decoded body");
+
+    assert_eq!(decoded, serde_json::to_value(&batch).unwrap());
+  }
+}
@@ -1,21 +1,87 @@
 
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
 use bevy::ecs::bundle::Bundle;
 use bevy::ecs::observer::Trigger;
-use bevy::ecs::system::{IntoObserverSystem, Res, ResMut, SystemParam};
-use bevy::log::{error, info};
-use bevy_mod_reqwest::reqwest::{Error as ReqwestError, Request};
-use bevy_mod_reqwest::{BevyReqwest, ReqwestErrorEvent, ReqwestResponseEvent};
+use bevy::ecs::system::{Commands, IntoObserverSystem, Res, ResMut, SystemParam};
+use bevy::image::Image;
+use bevy::log::{error, info, warn};
+use bevy_mod_reqwest::reqwest::{Error as ReqwestError, Method, Request, header::RETRY_AFTER};
+use bevy_mod_reqwest::{BevyReqwest, ReqwestErrorEvent, ReqwestResponseEvent, StatusCode};
 use serde::Serialize;
 use serde_json::json;
 
-use crate::api_types::{BatchEventPayload, FeedbackPayload};
+use crate::api_types::{
+  ApiResponse, BatchEventPayload, EventPayload, FeedbackPayload, HeartbeatPayload, PlayerIdentityPayload,
+  StartSessionResponse,
+};
 use crate::config::*;
-use crate::event::resources::BufferedEvents;
+use crate::delivery::{BatchDelivery, BatchResponse, handle_batch_response};
+use crate::event::resources::{BufferedEvents, QueuedEvent};
+use crate::identity::SettleIdentityChange;
+use crate::session::event_seq_generation;
+use crate::session::observers::is_retryable_status;
+use crate::session::resources::{ExpireSession, MetadataSnapshot, MetadataUpdate};
+use crate::transport::{EncodedBody, IndigaugeCompression, IndigaugeWireFormat, TransportState};
+use crate::worker::{DeliveryJob, DeliveryOutput, DeliveryWorker};
+
+#[cfg(not(target_family = "wasm"))]
+use crate::{
+  spool::{self, SpooledSession},
+  transport::{TransportError, encode_body},
+};
+#[cfg(not(target_family = "wasm"))]
+use std::path::PathBuf;
 
 pub fn select<T>(true_case: T, false_case: T, condition: bool) -> T {
   if condition { true_case } else { false_case }
 }
 
+/// Event batch delivery of the plugin, for batches sent with the session token `api_key`.
+struct EventBatchDelivery<'a, 'w, 's, 'cw, 'cs> {
+  ig: &'a mut BevyIndigauge<'w, 's>,
+  commands: &'a mut Commands<'cw, 'cs>,
+  api_key: &'a str,
+}
+
+impl BatchDelivery for EventBatchDelivery<'_, '_, '_, '_, '_> {
+  fn log_level(&self) -> &IndigaugeLogLevel {
+    &self.ig.log_level
+  }
+
+  fn transport(&mut self) -> &mut TransportState {
+    &mut self.ig.transport
+  }
+
+  fn retry_interval(&self) -> Duration {
+    self.ig.config.flush_interval
+  }
+
+  fn send_event_batch(&mut self, batch: BatchEventPayload) {
+    self.ig.send_event_batch(self.api_key, batch);
+  }
+
+  fn hold_back(&mut self, events: Vec<EventPayload>) {
+    self.ig.requeue_events(events);
+  }
+
+  fn session_expired(&mut self, events: Vec<EventPayload>) {
+    self.commands.queue(ExpireSession {
+      session_token: self.api_key.to_string(),
+      events,
+    });
+  }
+}
+
+/// Returns the current UTC wall-clock time as milliseconds since the unix epoch.
+pub(crate) fn unix_timestamp_ms() -> u64 {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map(|duration| duration.as_millis() as u64)
+    .unwrap_or_default()
+}
+
 #[derive(SystemParam)]
 pub struct BevyIndigauge<'w, 's> {
   pub reqwest_client: BevyReqwest<'w, 's>,
@@ -23,8 +89,12 @@ pub struct BevyIndigauge<'w, 's> {
   pub buffered_events: ResMut<'w, BufferedEvents>,
   pub log_level: Res<'w, IndigaugeLogLevel>,
   pub mode: Res<'w, IndigaugeMode>,
+  pub transport: ResMut<'w, TransportState>,
+  pub worker: Res<'w, DeliveryWorker>,
 }
 
+// Dev mode arms keep the log level check in their body, like the other log guards.
+#[allow(clippy::collapsible_match)]
 impl<'w, 's> BevyIndigauge<'w, 's> {
   pub(crate) fn build_post_request<S>(&self, url: &str, ig_key: &str, payload: &S) -> Result<Request, ReqwestError>
   where
@@ -42,56 +112,39 @@ impl<'w, 's> BevyIndigauge<'w, 's> {
       .build()
   }
 
-  pub(crate) fn build_patch_request<S>(&self, url: &str, ig_key: &str, payload: &S) -> Result<Request, ReqwestError>
-  where
-    S: Serialize,
-  {
+  /// Builds a request with an already encoded body, setting the matching content headers.
+  pub(crate) fn build_encoded_request(
+    &self,
+    method: Method,
+    url: &str,
+    ig_key: &str,
+    body: EncodedBody,
+  ) -> Result<Request, ReqwestError> {
     let url = format!("{}/v1/{}", &self.config.api_base, url);
 
-    self
+    let mut request = self
       .reqwest_client
-      .patch(url)
+      .request(method, url)
       .timeout(self.config.request_timeout)
-      .header("Content-Type", "application/json")
-      .header("X-Indigauge-Key", ig_key)
-      .json(payload)
-      .build()
+      .header("Content-Type", body.content_type)
+      .header("X-Indigauge-Key", ig_key);
+
+    if let Some(content_encoding) = body.content_encoding {
+      request = request.header("Content-Encoding", content_encoding);
+    }
+
+    request.body(body.bytes).build()
   }
 
-  pub(crate) fn send_feedback_screenshot(&mut self, api_key: &str, feedback_id: &str, image_data: Vec<u8>) {
+  /// Hands the screenshot to the delivery worker, which encodes it as PNG before it is uploaded.
+  pub(crate) fn send_feedback_screenshot(&mut self, api_key: &str, feedback_id: &str, image: Image) {
     match *self.mode {
       IndigaugeMode::Live => {
-        let url = format!("feedback/{}/screenshot", feedback_id);
-        let url = format!("{}/v1/{}", &self.config.api_base, url);
-
-        let request = self
-          .reqwest_client
-          .post(url)
-          .timeout(self.config.request_timeout)
-          .header("Content-Type", "image/png")
-          .header("X-Indigauge-Key", api_key)
-          .body(image_data)
-          .build();
-
-        if let Ok(request) = request {
-          self
-            .reqwest_client
-            .send(request)
-            .on_response(|trigger: Trigger<ReqwestResponseEvent>, log_level: Res<IndigaugeLogLevel>| {
-              if trigger.status().is_success() {
-                if *log_level <= IndigaugeLogLevel::Info {
-                  info!(message = "Sent feedback screenshot");
-                }
-              } else if *log_level <= IndigaugeLogLevel::Error {
-                error!(message = "Failed to send feedback screenshot");
-              }
-            })
-            .on_error(|trigger: Trigger<ReqwestErrorEvent>, log_level: Res<IndigaugeLogLevel>| {
-              if *log_level <= IndigaugeLogLevel::Error {
-                error!(message = "Failed to send feedback", error = ?trigger.event().0);
-              }
-            });
-        }
+        self.worker.submit(DeliveryJob::Screenshot {
+          api_key: api_key.to_string(),
+          feedback_id: feedback_id.to_string(),
+          image: Box::new(image),
+        });
       },
       IndigaugeMode::Dev => {
         if *self.log_level <= IndigaugeLogLevel::Info {
@@ -102,6 +155,40 @@ impl<'w, 's> BevyIndigauge<'w, 's> {
     }
   }
 
+  fn dispatch_feedback_screenshot(&mut self, api_key: &str, feedback_id: &str, image_data: Vec<u8>) {
+    let url = format!("feedback/{}/screenshot", feedback_id);
+    let url = format!("{}/v1/{}", &self.config.api_base, url);
+
+    let request = self
+      .reqwest_client
+      .post(url)
+      .timeout(self.config.request_timeout)
+      .header("Content-Type", "image/png")
+      .header("X-Indigauge-Key", api_key)
+      .body(image_data)
+      .build();
+
+    if let Ok(request) = request {
+      self
+        .reqwest_client
+        .send(request)
+        .on_response(|trigger: Trigger<ReqwestResponseEvent>, log_level: Res<IndigaugeLogLevel>| {
+          if trigger.status().is_success() {
+            if *log_level <= IndigaugeLogLevel::Info {
+              info!(message = "Sent feedback screenshot");
+            }
+          } else if *log_level <= IndigaugeLogLevel::Error {
+            error!(message = "Failed to send feedback screenshot");
+          }
+        })
+        .on_error(|trigger: Trigger<ReqwestErrorEvent>, log_level: Res<IndigaugeLogLevel>| {
+          if *log_level <= IndigaugeLogLevel::Error {
+            error!(message = "Failed to send feedback", error = ?trigger.event().0);
+          }
+        });
+    }
+  }
+
   pub(crate) fn send_feedback<RB, RM, OR>(&mut self, api_key: &str, payload: &FeedbackPayload, on_response: OR)
   where
     RB: Bundle,
@@ -128,89 +215,224 @@ impl<'w, 's> BevyIndigauge<'w, 's> {
     }
   }
 
+  /// Buffers events of a batch that wasn't accepted again, to be sent with a later batch.
+  fn requeue_events(&mut self, events: Vec<EventPayload>) {
+    for payload in events {
+      let mut event = QueuedEvent::new(payload, event_seq_generation());
+      event.measure();
+      self.buffered_events.push(event, &self.config);
+    }
+  }
+
+  /// Flushes up to `batch_size` events. Priority events are always sent ahead of regular events.
   pub(crate) fn flush_events(&mut self, api_key: &str) -> usize {
-    let event_len = self.buffered_events.events.len();
-    if event_len == 0 {
+    if self.buffered_events.is_empty() {
       return 0;
     }
 
-    let events = BatchEventPayload {
-      events: self
-        .buffered_events
-        .events
-        .drain(..(event_len.min(self.config.batch_size)))
-        .map(|event| event.into_inner())
-        .collect::<Vec<_>>(),
+    let events = self.buffered_events.take_batch(&self.config);
+    self.send_event_batch(api_key, BatchEventPayload::new(events))
+  }
+
+  /// Flushes up to `priority_batch_size` priority events without waiting for regular events.
+  pub(crate) fn flush_priority_events(&mut self, api_key: &str) -> usize {
+    if self.buffered_events.priority_events().is_empty() {
+      return 0;
+    }
+
+    let events = self.buffered_events.take_priority_batch(&self.config);
+    self.send_event_batch(api_key, BatchEventPayload::new(events))
+  }
+
+  /// Hands the batch to the delivery worker for serialization and compression. The request is sent once the
+  /// encoded body comes back, see [`BevyIndigauge::dispatch_delivery_outputs`].
+  fn send_event_batch(&mut self, api_key: &str, events: BatchEventPayload) -> usize {
+    let count = events.events.len();
+
+    match *self.mode {
+      IndigaugeMode::Live => {
+        let (wire_format, compression) = self.batch_encoding();
+        self.worker.submit(DeliveryJob::EventBatch {
+          api_key: api_key.to_string(),
+          batch: events,
+          wire_format,
+          compression,
+          compression_threshold: self.config.compression_threshold,
+        });
+      },
+      IndigaugeMode::Dev => {
+        if *self.log_level <= IndigaugeLogLevel::Info {
+          info!(message = "DEVMODE: sending event batch", count);
+        }
+      },
+      _ => {},
+    }
+
+    count
+  }
+
+  /// Wire format and compression for event batches, falling back to what the server accepts.
+  fn batch_encoding(&self) -> (IndigaugeWireFormat, IndigaugeCompression) {
+    (
+      select(IndigaugeWireFormat::Json, self.config.wire_format, self.transport.wire_format_rejected),
+      select(IndigaugeCompression::None, self.config.compression, self.transport.compression_rejected),
+    )
+  }
+
+  /// Encodes an event batch on the calling thread.
+  #[cfg(not(target_family = "wasm"))]
+  pub(crate) fn encode_event_batch(&self, batch: &BatchEventPayload) -> Result<EncodedBody, TransportError> {
+    let (wire_format, compression) = self.batch_encoding();
+    encode_body(batch, wire_format, compression, self.config.compression_threshold)
+  }
+
+  fn dispatch_event_batch(&mut self, api_key: String, batch: BatchEventPayload, body: EncodedBody) {
+    let encoding = body.encoding;
+
+    let request = match self.build_encoded_request(Method::POST, "events/batch", &api_key, body) {
+      Ok(request) => request,
+      Err(error) => {
+        if *self.log_level <= IndigaugeLogLevel::Error {
+          error!(message = "Failed to create event batch request", %error);
+        }
+        return;
+      },
     };
 
+    // Taken by whichever of the response and error observers runs.
+    let sent_batch = Arc::new(Mutex::new(Some(batch)));
+    let error_batch = sent_batch.clone();
+    let error_api_key = api_key.clone();
+
+    self
+      .reqwest_client
+      .send(request)
+      .on_response(move |trigger: Trigger<ReqwestResponseEvent>, mut ig: BevyIndigauge, mut commands: Commands| {
+        let Some(batch) = sent_batch.lock().ok().and_then(|mut batch| batch.take()) else {
+          return;
+        };
+        let response = BatchResponse::Status {
+          status: trigger.event().status(),
+          retry_after: trigger.event().response_headers().get(RETRY_AFTER).and_then(|value| value.to_str().ok()),
+        };
+        let mut delivery = EventBatchDelivery {
+          ig: &mut ig,
+          commands: &mut commands,
+          api_key: &api_key,
+        };
+        handle_batch_response(&mut delivery, batch, encoding, response);
+      })
+      .on_error(move |trigger: Trigger<ReqwestErrorEvent>, mut ig: BevyIndigauge, mut commands: Commands| {
+        let Some(batch) = error_batch.lock().ok().and_then(|mut batch| batch.take()) else {
+          return;
+        };
+        let mut delivery = EventBatchDelivery {
+          ig: &mut ig,
+          commands: &mut commands,
+          api_key: &error_api_key,
+        };
+        handle_batch_response(&mut delivery, batch, encoding, BatchResponse::Failed(&trigger.event().0));
+      });
+  }
+
+  pub(crate) fn send_heartbeat(&mut self, api_key: &str, payload: &HeartbeatPayload) {
     match *self.mode {
       IndigaugeMode::Live => {
-        if let Ok(request) = self.build_post_request("events/batch", api_key, &events) {
+        if let Ok(request) = self.build_post_request("sessions/heartbeat", api_key, payload) {
+          let api_key = api_key.to_string();
           self
             .reqwest_client
             .send(request)
-            .on_response(|trigger: Trigger<ReqwestResponseEvent>, log_level: Res<IndigaugeLogLevel>| {
-              let status = trigger.event().status();
-              if status.is_success() {
-                if *log_level <= IndigaugeLogLevel::Info {
-                  info!(message = "Event batch sent successfully");
+            .on_response(
+              move |trigger: Trigger<ReqwestResponseEvent>, log_level: Res<IndigaugeLogLevel>, mut commands: Commands| {
+                let status = trigger.event().status();
+                if status.is_success() {
+                  if *log_level <= IndigaugeLogLevel::Info {
+                    info!(message = "Heartbeat sent successfully");
+                  }
+                } else if status == StatusCode::UNAUTHORIZED {
+                  commands.queue(ExpireSession::new(&api_key));
+                } else if *log_level <= IndigaugeLogLevel::Error {
+                  error!(message = "Failed to update heartbeat", ?status);
                 }
-              } else if *log_level <= IndigaugeLogLevel::Error {
-                error!(message = "Failed to send event batch", ?status);
-              }
-            })
+              },
+            )
             .on_error(|trigger: Trigger<ReqwestErrorEvent>, log_level: Res<IndigaugeLogLevel>| {
               if *log_level <= IndigaugeLogLevel::Error {
-                error!(message = "Failed to send event batch", error = ?trigger.event().0);
+                error!(message = "Failed to send session heartbeat", error = ?trigger.event().0);
               }
             });
         }
       },
       IndigaugeMode::Dev => {
         if *self.log_level <= IndigaugeLogLevel::Info {
-          info!(message = "DEVMODE: sending event batch", count = events.events.len());
+          info!(message = "DEVMODE: heartbeat", ?payload);
         }
       },
       _ => {},
     }
-
-    events.events.len()
   }
 
-  pub(crate) fn send_heartbeat(&mut self, api_key: &str) {
+  /// Reports an identity change. Returns whether a request was sent, whose response settles the change, see
+  /// [`SettleIdentityChange`].
+  pub(crate) fn send_identity_change(&mut self, api_key: &str, payload: &PlayerIdentityPayload) -> bool {
     match *self.mode {
       IndigaugeMode::Live => {
-        if let Ok(request) = self.build_post_request("sessions/heartbeat", api_key, &json!({})) {
-          self
-            .reqwest_client
-            .send(request)
-            .on_response(|trigger: Trigger<ReqwestResponseEvent>, log_level: Res<IndigaugeLogLevel>| {
+        let Ok(request) = self.build_post_request("sessions/identity", api_key, payload) else {
+          return false;
+        };
+
+        let api_key = api_key.to_string();
+        let (change, failed_change) = (payload.clone(), payload.clone());
+        self
+          .reqwest_client
+          .send(request)
+          .on_response(
+            move |trigger: Trigger<ReqwestResponseEvent>, log_level: Res<IndigaugeLogLevel>, mut commands: Commands| {
               let status = trigger.event().status();
               if status.is_success() {
                 if *log_level <= IndigaugeLogLevel::Info {
-                  info!(message = "Heartbeat sent successfully");
+                  info!(message = "Player identity reported successfully");
                 }
+              } else if status == StatusCode::UNAUTHORIZED {
+                // Reported again once the continuation session is started.
+                commands.queue(ExpireSession::new(&api_key));
               } else if *log_level <= IndigaugeLogLevel::Error {
-                error!(message = "Failed to update heartbeat", ?status);
+                error!(message = "Failed to report player identity", ?status);
               }
-            })
-            .on_error(|trigger: Trigger<ReqwestErrorEvent>, log_level: Res<IndigaugeLogLevel>| {
+
+              let retry = !status.is_success() && (status == StatusCode::UNAUTHORIZED || is_retryable_status(status));
+              commands.queue(SettleIdentityChange {
+                change: change.clone(),
+                retry,
+              });
+            },
+          )
+          .on_error(
+            move |trigger: Trigger<ReqwestErrorEvent>, log_level: Res<IndigaugeLogLevel>, mut commands: Commands| {
               if *log_level <= IndigaugeLogLevel::Error {
-                error!(message = "Failed to send session heartbeat", error = ?trigger.event().0);
+                error!(message = "Failed to send player identity", error = ?trigger.event().0);
               }
-            });
-        }
+              commands.queue(SettleIdentityChange {
+                change: failed_change.clone(),
+                retry: true,
+              });
+            },
+          );
+        true
       },
       IndigaugeMode::Dev => {
         if *self.log_level <= IndigaugeLogLevel::Info {
-          info!("DEVMODE: heartbeat");
+          info!(message = "DEVMODE: player identity", ?payload);
         }
+        false
       },
-      _ => {},
+      _ => false,
     }
   }
 
-  pub(crate) fn update_metadata<T>(&mut self, meta: &T, api_key: &str)
+  /// Sends the changes to the session metadata since the last update. Nothing is sent if the metadata is unchanged.
+  pub(crate) fn update_metadata<T>(&mut self, meta: &T, api_key: &str, snapshot: &mut MetadataSnapshot)
   where
     T: Serialize,
   {
@@ -224,57 +446,327 @@ impl<'w, 's> BevyIndigauge<'w, 's> {
       },
     };
 
+    let Some(patch) = snapshot.patch(api_key, &metadata) else {
+      return;
+    };
+
     match *self.mode {
       IndigaugeMode::Live => {
-        if let Ok(request) = self.build_patch_request("sessions", api_key, &metadata) {
-          self
-            .reqwest_client
-            .send(request)
-            .on_response(|trigger: Trigger<ReqwestResponseEvent>, log_level: Res<IndigaugeLogLevel>| {
-              let status = trigger.event().status();
-              if status.is_success() {
-                if *log_level <= IndigaugeLogLevel::Info {
-                  info!(message = "Metadata updated successfully");
-                }
-              } else if *log_level <= IndigaugeLogLevel::Error {
-                error!(message = "Failed to update metadata", ?status);
-              }
-            })
-            .on_error(|trigger: Trigger<ReqwestErrorEvent>, log_level: Res<IndigaugeLogLevel>| {
-              if *log_level <= IndigaugeLogLevel::Error {
-                error!(message = "Failed to send session metadata update", error = ?trigger.event().0);
-              }
-            });
-        }
+        self.worker.submit(DeliveryJob::Metadata {
+          api_key: api_key.to_string(),
+          patch,
+          update: snapshot.sent(api_key, metadata),
+        });
       },
       IndigaugeMode::Dev => {
         if *self.log_level <= IndigaugeLogLevel::Info {
-          info!(message = "DEVMODE: update metadata", ?metadata);
+          info!(message = "DEVMODE: update metadata", ?patch);
         }
+        let update = snapshot.sent(api_key, metadata);
+        snapshot.accepted(update);
       },
       _ => {},
     }
   }
 
+  /// Sends a metadata update. It is recorded as accepted in the [`MetadataSnapshot`] once the server responds with
+  /// a success status. Updates that are never sent stay unconfirmed, so later updates are sent in full.
+  fn dispatch_metadata(&mut self, api_key: &str, body: EncodedBody, update: MetadataUpdate) {
+    if let Ok(request) = self.build_encoded_request(Method::PATCH, "sessions", api_key, body) {
+      let api_key = api_key.to_string();
+      // Taken by whichever of the response and error observers runs.
+      let sent_update = Arc::new(Mutex::new(Some(update)));
+      let error_update = sent_update.clone();
+
+      self
+        .reqwest_client
+        .send(request)
+        .on_response(
+          move |trigger: Trigger<ReqwestResponseEvent>,
+                log_level: Res<IndigaugeLogLevel>,
+                mut snapshot: ResMut<MetadataSnapshot>,
+                mut commands: Commands| {
+            let Some(update) = sent_update.lock().ok().and_then(|mut update| update.take()) else {
+              return;
+            };
+
+            let status = trigger.event().status();
+            if status.is_success() {
+              if *log_level <= IndigaugeLogLevel::Info {
+                info!(message = "Metadata updated successfully");
+              }
+              snapshot.accepted(update);
+            } else if status == StatusCode::UNAUTHORIZED {
+              // The full metadata is sent again once the continuation session is started.
+              snapshot.rejected(&update);
+              commands.queue(ExpireSession::new(&api_key));
+            } else {
+              if *log_level <= IndigaugeLogLevel::Error {
+                error!(message = "Failed to update metadata", ?status);
+              }
+              snapshot.rejected(&update);
+            }
+          },
+        )
+        .on_error(
+          move |trigger: Trigger<ReqwestErrorEvent>,
+                log_level: Res<IndigaugeLogLevel>,
+                mut snapshot: ResMut<MetadataSnapshot>| {
+            if *log_level <= IndigaugeLogLevel::Error {
+              error!(message = "Failed to send session metadata update", error = ?trigger.event().0);
+            }
+            if let Some(update) = error_update.lock().ok().and_then(|mut update| update.take()) {
+              snapshot.rejected(&update);
+            }
+          },
+        );
+    }
+  }
+
+  /// Sends a session stored on disk by an earlier launch, registering it first if it never was. The file is
+  /// removed once its events were accepted or the server rejected them, otherwise it is retried on the next launch.
+  #[cfg(not(target_family = "wasm"))]
+  pub(crate) fn register_spooled_session(&mut self, path: PathBuf, session: SpooledSession) {
+    match session.session_token.clone() {
+      Some(session_token) => self.send_spooled_session(path, &session_token, session, true),
+      None => self.start_spooled_session(path, session, true),
+    }
+  }
+
+  /// Registers a stored session with `sessions/start`. If its token is rejected later on, it is registered again once
+  /// if `can_restart` is set.
+  #[cfg(not(target_family = "wasm"))]
+  fn start_spooled_session(&mut self, path: PathBuf, session: SpooledSession, can_restart: bool) {
+    let Some(start) = &session.start else {
+      spool::remove(&path);
+      return;
+    };
+
+    let Ok(request) = self.build_post_request("sessions/start", &self.config.public_key, start) else {
+      return;
+    };
+
+    let mut spooled = Some((path, session));
+
+    self
+      .reqwest_client
+      .send(request)
+      .on_response(move |trigger: Trigger<ReqwestResponseEvent>, mut ig: BevyIndigauge| {
+        let Some((path, session)) = spooled.take() else {
+          return;
+        };
+
+        let status = trigger.event().status();
+        match trigger.event().deserialize_json::<ApiResponse<StartSessionResponse>>() {
+          Ok(ApiResponse::Ok(response)) => {
+            ig.send_spooled_session(path, &response.session_token, session, can_restart);
+          },
+          _ if !is_retryable_status(status) => {
+            if *ig.log_level <= IndigaugeLogLevel::Warn {
+              warn!(message = "Stored session was rejected, deleting it", ?status);
+            }
+            spool::remove(&path);
+          },
+          _ => {
+            if *ig.log_level <= IndigaugeLogLevel::Warn {
+              warn!(message = "Failed to register stored session", ?status);
+            }
+          },
+        }
+      })
+      .on_error(|trigger: Trigger<ReqwestErrorEvent>, log_level: Res<IndigaugeLogLevel>| {
+        if *log_level <= IndigaugeLogLevel::Warn {
+          warn!(message = "Failed to register stored session", error = ?trigger.event().0);
+        }
+      });
+  }
+
+  /// Sends the events of a stored session, followed by its metadata and end. If the token of the session was
+  /// rejected, the session is registered again as a continuation, see [`Self::restart_spooled_session`].
+  #[cfg(not(target_family = "wasm"))]
+  fn send_spooled_session(&mut self, path: PathBuf, session_token: &str, session: SpooledSession, can_restart: bool) {
+    let mut followups = Vec::new();
+
+    if let Some(metadata) = &session.metadata
+      && let Ok(body) = encode_body(metadata, IndigaugeWireFormat::Json, IndigaugeCompression::None, usize::MAX)
+      && let Ok(request) = self.build_encoded_request(Method::PATCH, "sessions", session_token, body)
+    {
+      followups.push(request);
+    }
+
+    if let Some(mut end) = session.end.clone() {
+      if let Some(end) = end.as_object_mut() {
+        end.insert("endedAt".to_string(), session.ended_at.into());
+      }
+      if let Ok(request) = self.build_post_request("sessions/end", session_token, &end) {
+        followups.push(request);
+      }
+    }
+
+    if session.events.is_empty() {
+      spool::remove(&path);
+      for request in followups {
+        self.reqwest_client.send(request);
+      }
+      return;
+    }
+
+    let batch = json!({ "events": &session.events, "localSessionId": &session.local_session_id });
+    let Ok(batch_request) = self.build_post_request("events/batch", session_token, &batch) else {
+      return;
+    };
+
+    let mut followups = Some(followups);
+    let mut spooled = Some(session);
+
+    self
+      .reqwest_client
+      .send(batch_request)
+      .on_response(move |trigger: Trigger<ReqwestResponseEvent>, mut ig: BevyIndigauge| {
+        let status = trigger.event().status();
+        if status.is_success()
+          && let Some(followups) = followups.take()
+        {
+          spool::remove(&path);
+          for request in followups {
+            ig.reqwest_client.send(request);
+          }
+
+          if *ig.log_level <= IndigaugeLogLevel::Info {
+            info!(message = "Stored session sent successfully");
+          }
+        } else if status == StatusCode::UNAUTHORIZED
+          && can_restart
+          && let Some(session) = spooled.take()
+        {
+          ig.restart_spooled_session(path.clone(), session);
+        } else if !is_retryable_status(status) {
+          if *ig.log_level <= IndigaugeLogLevel::Warn {
+            warn!(message = "Events of stored session were rejected, deleting it", ?status);
+          }
+          spool::remove(&path);
+        } else if *ig.log_level <= IndigaugeLogLevel::Warn {
+          warn!(message = "Failed to send events of stored session", ?status);
+        }
+      })
+      .on_error(|trigger: Trigger<ReqwestErrorEvent>, log_level: Res<IndigaugeLogLevel>| {
+        if *log_level <= IndigaugeLogLevel::Warn {
+          warn!(message = "Failed to send events of stored session", error = ?trigger.event().0);
+        }
+      });
+  }
+
+  /// Registers a stored session again after its token was rejected, as a continuation of the rejected session. Stored
+  /// sessions without the body of their `sessions/start` request can't be registered again and are deleted.
   #[cfg(not(target_family = "wasm"))]
-  pub(crate) fn get_or_init_player_id(&self) -> String {
-    use std::fs;
-    use uuid::Uuid;
-    let game_folder_path = dirs::preference_dir().map(|dir| dir.join(&self.config.game_name));
-
-    if let Some(game_folder_path) = game_folder_path {
-      let player_id_file_path = game_folder_path.join("player_id.txt");
-
-      if let Ok(player_id) = fs::read_to_string(&player_id_file_path) {
-        player_id
-      } else {
-        let new_player_id = Uuid::new_v4().to_string();
-        let _ = fs::create_dir_all(&game_folder_path);
-        let _ = fs::write(&player_id_file_path, &new_player_id);
-        new_player_id
+  fn restart_spooled_session(&mut self, path: PathBuf, mut session: SpooledSession) {
+    let rejected_token = session.session_token.take();
+    let Some(start) = session.start.as_mut().and_then(serde_json::Value::as_object_mut) else {
+      if *self.log_level <= IndigaugeLogLevel::Warn {
+        warn!("Token of stored session was rejected, deleting it");
       }
+      spool::remove(&path);
+      return;
+    };
+
+    start.insert("continuesSession".to_string(), rejected_token.into());
+    self.start_spooled_session(path, session, false);
+  }
+
+  /// Sends a request and blocks until the response arrives or the deadline passes. Returns whether the request
+  /// was accepted.
+  #[cfg(not(target_family = "wasm"))]
+  pub(crate) fn send_blocking(&self, mut request: Request, deadline: Instant) -> bool {
+    let remaining = deadline.saturating_duration_since(Instant::now());
+    if remaining.is_zero() {
+      return false;
+    }
+    *request.timeout_mut() = Some(remaining);
+
+    // The request is created inside the compat runtime too, since its timeout needs a Tokio reactor.
+    let response = bevy::tasks::block_on(async_compat::Compat::new(async {
+      self.reqwest_client.execute(request).await
+    }));
+    response.is_ok_and(|response| response.status().is_success())
+  }
+
+  /// Sends the requests for every job the delivery worker has finished so far.
+  pub(crate) fn dispatch_delivery_outputs(&mut self) {
+    while let Some(output) = self.worker.try_recv() {
+      self.dispatch_delivery_output(output);
+    }
+  }
+
+  pub(crate) fn dispatch_delivery_output(&mut self, output: DeliveryOutput) {
+    match output {
+      DeliveryOutput::EventBatch { api_key, batch, body } => match body {
+        Ok(body) => self.dispatch_event_batch(api_key, batch, body),
+        Err(error) => {
+          if *self.log_level <= IndigaugeLogLevel::Error {
+            error!(message = "Failed to create event batch request", %error);
+          }
+        },
+      },
+      DeliveryOutput::Metadata { api_key, body, update } => match body {
+        Ok(body) => self.dispatch_metadata(&api_key, body, update),
+        Err(error) => {
+          if *self.log_level <= IndigaugeLogLevel::Error {
+            error!(message = "Failed to serialize metadata", %error);
+          }
+        },
+      },
+      DeliveryOutput::Screenshot {
+        api_key,
+        feedback_id,
+        png,
+      } => match png {
+        Ok(png) => self.dispatch_feedback_screenshot(&api_key, &feedback_id, png),
+        Err(error) => {
+          if *self.log_level <= IndigaugeLogLevel::Error {
+            error!(message = "Failed to encode feedback screenshot", %error);
+          }
+        },
+      },
+      DeliveryOutput::EndSession { api_key, body } => {
+        match body.map(|body| self.build_encoded_request(Method::POST, "sessions/end", &api_key, body)) {
+          Ok(Ok(request)) => {
+            self.reqwest_client.send(request);
+          },
+          Ok(Err(error)) => {
+            if *self.log_level <= IndigaugeLogLevel::Error {
+              error!(message = "Failed to create session end request", %error);
+            }
+          },
+          Err(error) => {
+            if *self.log_level <= IndigaugeLogLevel::Error {
+              error!(message = "Failed to serialize session end", %error);
+            }
+          },
+        }
+      },
+    }
+  }
+}
+
+/// Reads the persistent player id for the game, creating it on first use.
+#[cfg(not(target_family = "wasm"))]
+pub(crate) fn get_or_init_player_id(game_name: &str) -> String {
+  use std::fs;
+  use uuid::Uuid;
+  let game_folder_path = dirs::preference_dir().map(|dir| dir.join(game_name));
+
+  if let Some(game_folder_path) = game_folder_path {
+    let player_id_file_path = game_folder_path.join("player_id.txt");
+
+    if let Ok(player_id) = fs::read_to_string(&player_id_file_path) {
+      player_id
     } else {
-      Uuid::new_v4().to_string()
+      let new_player_id = Uuid::new_v4().to_string();
+      let _ = fs::create_dir_all(&game_folder_path);
+      let _ = fs::write(&player_id_file_path, &new_player_id);
+      new_player_id
     }
+  } else {
+    Uuid::new_v4().to_string()
   }
 }
@@ -0,0 +1,259 @@
+use std::{
+  sync::atomic::{AtomicUsize, Ordering},
+  time::Duration,
+};
+
+use bevy::prelude::*;
+use crossbeam_channel::{Receiver, RecvTimeoutError, Sender, unbounded};
+use image::{ColorType, ImageEncoder, codecs::png::PngEncoder};
+
+use crate::{
+  api_types::BatchEventPayload,
+  config::IndigaugeLogLevel,
+  session::resources::MetadataUpdate,
+  transport::{EncodedBody, IndigaugeCompression, IndigaugeWireFormat, TransportError, encode_body},
+  utils::BevyIndigauge,
+};
+
+/// Work that is too expensive to do inside a frame. Jobs are processed by the delivery worker
+/// and come back as [`DeliveryOutput`]s, ready to be sent.
+pub(crate) enum DeliveryJob {
+  EventBatch {
+    api_key: String,
+    batch: BatchEventPayload,
+    wire_format: IndigaugeWireFormat,
+    compression: IndigaugeCompression,
+    compression_threshold: usize,
+  },
+  Metadata {
+    api_key: String,
+    /// Merge patch that is sent.
+    patch: serde_json::Value,
+    update: MetadataUpdate,
+  },
+  Screenshot {
+    api_key: String,
+    feedback_id: String,
+    image: Box<Image>,
+  },
+  /// Queued behind the final event batches and metadata of the session, so that it is sent after them.
+  EndSession {
+    api_key: String,
+    payload: serde_json::Value,
+  },
+}
+
+pub(crate) enum DeliveryOutput {
+  EventBatch {
+    api_key: String,
+    batch: BatchEventPayload,
+    body: Result<EncodedBody, TransportError>,
+  },
+  Metadata {
+    api_key: String,
+    body: Result<EncodedBody, TransportError>,
+    update: MetadataUpdate,
+  },
+  Screenshot {
+    api_key: String,
+    feedback_id: String,
+    png: Result<Vec<u8>, String>,
+  },
+  EndSession {
+    api_key: String,
+    body: Result<EncodedBody, TransportError>,
+  },
+}
+
+/// Background worker that serializes, compresses and encodes request bodies off the main thread.
+///
+/// Batching and sending stay in the ECS: batches are cut when events are flushed, using the size measured when each
+/// event was buffered, and the requests are sent once their bodies come back from the worker. The ECS only talks to
+/// the worker through channels. On wasm, where threads are not available, or if the worker thread could not be
+/// started, jobs are processed on submit instead.
+#[derive(Resource)]
+pub struct DeliveryWorker {
+  /// `None` if jobs are processed on submit.
+  #[cfg(not(target_family = "wasm"))]
+  jobs: Option<Sender<DeliveryJob>>,
+  outputs: Receiver<DeliveryOutput>,
+  outputs_tx: Sender<DeliveryOutput>,
+  pending: AtomicUsize,
+}
+
+impl DeliveryWorker {
+  #[cfg_attr(target_family = "wasm", allow(unused_variables))]
+  pub(crate) fn spawn(log_level: &IndigaugeLogLevel) -> Self {
+    let (outputs_tx, outputs_rx) = unbounded::<DeliveryOutput>();
+
+    #[cfg(not(target_family = "wasm"))]
+    {
+      let (jobs_tx, jobs_rx) = unbounded::<DeliveryJob>();
+      let worker_outputs_tx = outputs_tx.clone();
+      let spawned = std::thread::Builder::new()
+        .name("indigauge-delivery".to_string())
+        .spawn(move || {
+          for job in jobs_rx.iter() {
+            if worker_outputs_tx.send(process_job(job)).is_err() {
+              break;
+            }
+          }
+        });
+
+      let jobs = match spawned {
+        Ok(_) => Some(jobs_tx),
+        Err(error) => {
+          if *log_level <= IndigaugeLogLevel::Warn {
+            warn!(message = "Failed to spawn Indigauge delivery worker, encoding requests on the main thread", ?error);
+          }
+          None
+        },
+      };
+
+      Self {
+        jobs,
+        outputs: outputs_rx,
+        outputs_tx,
+        pending: AtomicUsize::new(0),
+      }
+    }
+
+    #[cfg(target_family = "wasm")]
+    {
+      Self {
+        outputs: outputs_rx,
+        outputs_tx,
+        pending: AtomicUsize::new(0),
+      }
+    }
+  }
+
+  /// Processes jobs on submit, as if the worker thread could not be started.
+  #[cfg(test)]
+  pub(crate) fn inline() -> Self {
+    let (outputs_tx, outputs) = unbounded::<DeliveryOutput>();
+    Self {
+      #[cfg(not(target_family = "wasm"))]
+      jobs: None,
+      outputs,
+      outputs_tx,
+      pending: AtomicUsize::new(0),
+    }
+  }
+
+  pub(crate) fn submit(&self, job: DeliveryJob) {
+    self.pending.fetch_add(1, Ordering::Relaxed);
+
+    #[cfg(not(target_family = "wasm"))]
+    let submitted = match &self.jobs {
+      Some(jobs) => jobs.send(job).is_ok(),
+      None => self.outputs_tx.send(process_job(job)).is_ok(),
+    };
+
+    #[cfg(target_family = "wasm")]
+    let submitted = self.outputs_tx.send(process_job(job)).is_ok();
+
+    if !submitted {
+      self.pending.fetch_sub(1, Ordering::Relaxed);
+    }
+  }
+
+  /// Number of submitted jobs whose output has not been received yet.
+  pub(crate) fn pending(&self) -> usize {
+    self.pending.load(Ordering::Relaxed)
+  }
+
+  pub(crate) fn try_recv(&self) -> Option<DeliveryOutput> {
+    let output = self.outputs.try_recv().ok()?;
+    self.pending.fetch_sub(1, Ordering::Relaxed);
+    Some(output)
+  }
+
+  pub(crate) fn recv_timeout(&self, timeout: Duration) -> Result<DeliveryOutput, RecvTimeoutError> {
+    let output = self.outputs.recv_timeout(timeout)?;
+    self.pending.fetch_sub(1, Ordering::Relaxed);
+    Ok(output)
+  }
+}
+
+pub(crate) fn process_job(job: DeliveryJob) -> DeliveryOutput {
+  match job {
+    DeliveryJob::EventBatch {
+      api_key,
+      batch,
+      wire_format,
+      compression,
+      compression_threshold,
+    } => {
+      let body = encode_body(&batch, wire_format, compression, compression_threshold);
+      DeliveryOutput::EventBatch { api_key, batch, body }
+    },
+    DeliveryJob::Metadata { api_key, patch, update } => {
+      let body = encode_body(&patch, IndigaugeWireFormat::Json, IndigaugeCompression::None, usize::MAX);
+      DeliveryOutput::Metadata { api_key, body, update }
+    },
+    DeliveryJob::Screenshot {
+      api_key,
+      feedback_id,
+      image,
+    } => DeliveryOutput::Screenshot {
+      api_key,
+      feedback_id,
+      png: encode_png(*image),
+    },
+    DeliveryJob::EndSession { api_key, payload } => {
+      let body = encode_body(&payload, IndigaugeWireFormat::Json, IndigaugeCompression::None, usize::MAX);
+      DeliveryOutput::EndSession { api_key, body }
+    },
+  }
+}
+
+fn encode_png(image: Image) -> Result<Vec<u8>, String> {
+  let dyn_img = image
+    .try_into_dynamic()
+    .map_err(|error| format!("Failed to convert screenshot into dynamic image: {error:?}"))?;
+
+  let data = dyn_img.to_rgb8().to_vec();
+  let mut png = Vec::new();
+
+  PngEncoder::new(&mut png)
+    .write_image(&data, dyn_img.width(), dyn_img.height(), ColorType::Rgb8)
+    .map_err(|error| format!("Failed to encode screenshot as PNG: {error}"))?;
+
+  Ok(png)
+}
+
+/// Sends the requests for every job the delivery worker has finished.
+pub(crate) fn dispatch_delivery_outputs(mut ig: BevyIndigauge) {
+  ig.dispatch_delivery_outputs();
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn worker_encodes_event_batches_off_thread_or_inline() {
+    for worker in [DeliveryWorker::spawn(&IndigaugeLogLevel::Info), DeliveryWorker::inline()] {
+      worker.submit(DeliveryJob::EventBatch {
+        api_key: "key".to_string(),
+        batch: BatchEventPayload {
+          events: Vec::new(),
+          local_session_id: None,
+        },
+        wire_format: IndigaugeWireFormat::Json,
+        compression: IndigaugeCompression::None,
+        compression_threshold: 0,
+      });
+      assert_eq!(worker.pending(), 1);
+
+      let Ok(DeliveryOutput::EventBatch { api_key, body, .. }) = worker.recv_timeout(Duration::from_secs(5)) else {
+        panic!("Expected an encoded event batch");
+      };
+
+      assert_eq!(api_key, "key");
+      assert_eq!(body.expect("Encoded body").bytes, br#"{"events":[]}"#);
+      assert_eq!(worker.pending(), 0);
+    }
+  }
+}